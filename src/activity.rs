@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{http::Request, middleware::Next, response::Response};
+
+use tokio::sync::watch;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::network::PortalState;
+use crate::nl80211::session::Nl80211Session;
+use crate::nl80211::station::count_stations;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Remembers when someone last used the portal, either by sending an HTTP
+/// request to a portal listener or by being associated with the access point.
+#[derive(Clone)]
+pub struct Activity {
+    last_seen: Arc<Mutex<Instant>>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            last_seen: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn touch(&self) {
+        self.touch_at(Instant::now());
+    }
+
+    fn touch_at(&self, now: Instant) {
+        *self.last_seen.lock().unwrap() = now;
+    }
+
+    fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(*self.last_seen.lock().unwrap())
+    }
+}
+
+/// Counts down while the portal is active, from the last activity or from
+/// the moment the portal came up, whichever is later.
+struct InactivityCheck {
+    activity: Activity,
+    timeout: Duration,
+    was_active: bool,
+}
+
+impl InactivityCheck {
+    fn new(activity: Activity, timeout: Duration) -> Self {
+        Self {
+            activity,
+            timeout,
+            was_active: false,
+        }
+    }
+
+    /// `stations` is the number of clients associated with the access point,
+    /// when it is known.
+    fn is_inactive(&mut self, now: Instant, portal_active: bool, stations: Option<usize>) -> bool {
+        if !portal_active {
+            self.was_active = false;
+            return false;
+        }
+
+        if !self.was_active {
+            self.activity.touch_at(now);
+            self.was_active = true;
+        }
+
+        if stations.map_or(false, |stations| stations > 0) {
+            self.activity.touch_at(now);
+        }
+
+        self.activity.idle_for(now) >= self.timeout
+    }
+}
+
+pub async fn track_activity<B>(req: Request<B>, next: Next<B>, activity: Activity) -> Response {
+    activity.touch();
+
+    next.run(req).await
+}
+
+/// Resolves once the portal has been up for `timeout` without any HTTP
+/// requests or associated clients. The countdown only runs while the portal
+/// is active and restarts every time it comes up.
+pub async fn wait_for_inactivity(
+    activity: Activity,
+    timeout: Duration,
+    session: Option<Nl80211Session>,
+    interface: String,
+    portal_state: watch::Receiver<PortalState>,
+) {
+    let mut ticks = interval(CHECK_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut check = InactivityCheck::new(activity, timeout);
+
+    loop {
+        ticks.tick().await;
+
+        let is_active = *portal_state.borrow() == PortalState::Active;
+
+        let stations = match session {
+            Some(ref session) if is_active => match count_stations(session, &interface).await {
+                Ok(stations) => Some(stations),
+                Err(err) => {
+                    println!("Failed to count portal clients: {:#}", err);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        if check.is_inactive(Instant::now(), is_active, stations) {
+            println!("No portal activity for {} seconds", timeout.as_secs());
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn after(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn times_out_without_activity() {
+        let start = Instant::now();
+        let mut check = InactivityCheck::new(Activity::new(), TIMEOUT);
+
+        assert!(!check.is_inactive(start, true, Some(0)));
+        assert!(!check.is_inactive(after(start, 59), true, Some(0)));
+        assert!(check.is_inactive(after(start, 60), true, Some(0)));
+    }
+
+    #[test]
+    fn http_activity_restarts_countdown() {
+        let start = Instant::now();
+        let activity = Activity::new();
+        let mut check = InactivityCheck::new(activity.clone(), TIMEOUT);
+
+        assert!(!check.is_inactive(start, true, None));
+
+        activity.touch_at(after(start, 50));
+
+        assert!(!check.is_inactive(after(start, 100), true, None));
+        assert!(check.is_inactive(after(start, 110), true, None));
+    }
+
+    #[test]
+    fn associated_stations_count_as_activity() {
+        let start = Instant::now();
+        let mut check = InactivityCheck::new(Activity::new(), TIMEOUT);
+
+        assert!(!check.is_inactive(start, true, Some(1)));
+        assert!(!check.is_inactive(after(start, 100), true, Some(2)));
+        assert!(!check.is_inactive(after(start, 150), true, Some(0)));
+        assert!(check.is_inactive(after(start, 160), true, Some(0)));
+    }
+
+    #[test]
+    fn counts_down_only_while_portal_is_active() {
+        let start = Instant::now();
+        let mut check = InactivityCheck::new(Activity::new(), TIMEOUT);
+
+        assert!(!check.is_inactive(start, false, None));
+        assert!(!check.is_inactive(after(start, 100), false, None));
+
+        // The countdown starts when the portal comes up
+        assert!(!check.is_inactive(after(start, 200), true, None));
+        assert!(!check.is_inactive(after(start, 259), true, None));
+
+        // And restarts when it comes up again
+        assert!(!check.is_inactive(after(start, 300), false, None));
+        assert!(!check.is_inactive(after(start, 400), true, None));
+        assert!(check.is_inactive(after(start, 460), true, None));
+    }
+}
@@ -0,0 +1,146 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use axum::{
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, HOST, LOCATION},
+        HeaderMap, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use tokio::sync::watch;
+
+use crate::network::PortalState;
+
+const APPLE_SUCCESS: &str = "<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>";
+const WINDOWS_NCSI_SUCCESS: &str = "Microsoft NCSI";
+const WINDOWS_CONNECT_TEST_SUCCESS: &str = "Microsoft Connect Test";
+const FIREFOX_SUCCESS: &str = "success\n";
+
+/// Connectivity checks issued by operating systems and browsers in order to
+/// detect captive portals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    Android,
+    Apple,
+    WindowsNcsi,
+    WindowsConnectTest,
+    Firefox,
+}
+
+impl Probe {
+    fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/generate_204" | "/gen_204" => Some(Self::Android),
+            "/hotspot-detect.html" | "/library/test/success.html" => Some(Self::Apple),
+            "/ncsi.txt" => Some(Self::WindowsNcsi),
+            "/connecttest.txt" => Some(Self::WindowsConnectTest),
+            "/success.txt" => Some(Self::Firefox),
+            _ => None,
+        }
+    }
+
+    /// The response each client expects when there is no captive portal.
+    fn success_response(self) -> Response {
+        match self {
+            Self::Android => StatusCode::NO_CONTENT.into_response(),
+            Self::Apple => text_response("text/html", APPLE_SUCCESS),
+            Self::WindowsNcsi => text_response("text/plain", WINDOWS_NCSI_SUCCESS),
+            Self::WindowsConnectTest => text_response("text/plain", WINDOWS_CONNECT_TEST_SUCCESS),
+            Self::Firefox => text_response("text/plain", FIREFOX_SUCCESS),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CaptiveState {
+    portal_state: watch::Receiver<PortalState>,
+    gateway: Ipv4Addr,
+}
+
+impl CaptiveState {
+    pub fn new(portal_state: watch::Receiver<PortalState>, gateway: Ipv4Addr) -> Self {
+        Self {
+            portal_state,
+            gateway,
+        }
+    }
+}
+
+/// Middleware redirecting connectivity probes and requests for foreign
+/// hosts to the portal UI while the portal is active, and answering the
+/// probes with their success responses once the device is provisioned.
+pub async fn handle_captive_probes<B>(
+    req: Request<B>,
+    next: Next<B>,
+    state: CaptiveState,
+) -> Response {
+    let portal_state = *state.portal_state.borrow();
+    let probe = Probe::from_path(req.uri().path());
+
+    match portal_state {
+        PortalState::Active => {
+            if probe.is_some() || is_foreign_host(req.headers()) {
+                return redirect_to_portal(req.headers(), state.gateway);
+            }
+        }
+        PortalState::Provisioned => {
+            if let Some(probe) = probe {
+                return probe.success_response();
+            }
+        }
+        PortalState::Inactive => {}
+    }
+
+    next.run(req).await
+}
+
+/// Requests for host names other than `localhost` only reach us because the
+/// DNS responder resolves every name to the gateway. Plain IP addresses are
+/// used by clients that talk to the API directly.
+fn is_foreign_host(headers: &HeaderMap) -> bool {
+    match host_and_port(headers) {
+        Some((host, _)) => host != "localhost" && host.parse::<IpAddr>().is_err(),
+        None => false,
+    }
+}
+
+fn redirect_to_portal(headers: &HeaderMap, gateway: Ipv4Addr) -> Response {
+    // Keep the port the client used, so that the redirect reaches the same
+    // listener
+    let location = match host_and_port(headers).and_then(|(_, port)| port) {
+        Some(port) => format!("http://{}:{}/", gateway, port),
+        None => format!("http://{}/", gateway),
+    };
+
+    (
+        StatusCode::FOUND,
+        [(LOCATION, location), (CACHE_CONTROL, "no-store".to_owned())],
+    )
+        .into_response()
+}
+
+fn host_and_port(headers: &HeaderMap) -> Option<(&str, Option<&str>)> {
+    let host = headers.get(HOST)?.to_str().ok()?;
+
+    // IPv6 literals are enclosed in brackets
+    if let Some(rest) = host.strip_prefix('[') {
+        let (address, rest) = rest.split_once(']')?;
+        return Some((address, rest.strip_prefix(':')));
+    }
+
+    match host.split_once(':') {
+        Some((host, port)) => Some((host, Some(port))),
+        None => Some((host, None)),
+    }
+}
+
+fn text_response(content_type: &'static str, body: &'static str) -> Response {
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, content_type), (CACHE_CONTROL, "no-store")],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,777 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use macaddr::MacAddr6;
+
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+use crate::network::{wait_for_portal_active, PortalState};
+use crate::opts::Opts;
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const MAX_PACKET_SIZE: usize = 1500;
+const MIN_PACKET_SIZE: usize = 300;
+
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+const OP_BOOTREQUEST: u8 = 1;
+const OP_BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const HLEN_ETHERNET: u8 = 6;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+// Offsets into the fixed BOOTP header
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const CIADDR_OFFSET: usize = 12;
+const YIADDR_OFFSET: usize = 16;
+const GIADDR_OFFSET: usize = 24;
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+const OPTIONS_OFFSET: usize = 240;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_IDENTIFIER: u8 = 54;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPRELEASE: u8 = 7;
+const DHCPINFORM: u8 = 8;
+
+const SUBNET_MASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+
+#[derive(Debug, Clone)]
+pub struct DhcpConfig {
+    pub gateway: Ipv4Addr,
+    pub range_start: Ipv4Addr,
+    pub range_end: Ipv4Addr,
+    pub lease_time: Duration,
+}
+
+impl DhcpConfig {
+    /// Derives the lease pool from the /24 network of the gateway unless
+    /// a range is given explicitly.
+    pub fn from_opts(opts: &Opts, gateway: Ipv4Addr) -> Result<Self> {
+        let [a, b, c, _] = gateway.octets();
+
+        let range_start = opts
+            .dhcp_range_start
+            .unwrap_or_else(|| Ipv4Addr::new(a, b, c, 2));
+        let range_end = opts
+            .dhcp_range_end
+            .unwrap_or_else(|| Ipv4Addr::new(a, b, c, 254));
+
+        if !is_same_network(range_start, gateway) || !is_same_network(range_end, gateway) {
+            bail!("DHCP range must be within the gateway /24 network");
+        }
+
+        if range_start > range_end {
+            bail!("DHCP range start must not be after the range end");
+        }
+
+        Ok(Self {
+            gateway,
+            range_start,
+            range_end,
+            lease_time: Duration::from_secs(opts.dhcp_lease_time.into()),
+        })
+    }
+}
+
+/// Runs the DHCP server on `interface` for as long as the portal is active.
+pub async fn run_dhcp_server(
+    config: DhcpConfig,
+    interface: String,
+    mut portal_state: watch::Receiver<PortalState>,
+) {
+    loop {
+        if !wait_for_portal_active(&mut portal_state, true).await {
+            return;
+        }
+
+        match bind_socket(&interface) {
+            Ok(socket) => {
+                println!("DHCP server listening on {}", interface);
+
+                let mut server = DhcpServer::new(config.clone());
+
+                tokio::select! {
+                    result = server.serve(&socket) => {
+                        if let Err(err) = result {
+                            println!("DHCP server failed: {:#}", err);
+                        }
+                    },
+                    _ = wait_for_portal_active(&mut portal_state, false) => {},
+                }
+
+                println!("DHCP server stopped");
+            }
+            Err(err) => println!("Failed to start DHCP server: {:#}", err),
+        }
+
+        if !wait_for_portal_active(&mut portal_state, false).await {
+            return;
+        }
+    }
+}
+
+fn bind_socket(interface: &str) -> Result<UdpSocket> {
+    let socket = std::net::UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, SERVER_PORT)))
+        .context("Failed to bind DHCP server socket")?;
+
+    // Clients without an address broadcast their requests, so the socket
+    // has to be bound to the wildcard address. Restrict it to the portal
+    // interface so that we never answer on any other network.
+    bind_to_device(&socket, interface)?;
+
+    socket
+        .set_broadcast(true)
+        .context("Failed to enable broadcast on DHCP server socket")?;
+    socket
+        .set_nonblocking(true)
+        .context("Failed to make DHCP server socket non-blocking")?;
+
+    UdpSocket::from_std(socket).context("Failed to register DHCP server socket")
+}
+
+fn bind_to_device(socket: &std::net::UdpSocket, interface: &str) -> Result<()> {
+    let name = CString::new(interface).context("Invalid interface name")?;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr().cast(),
+            name.as_bytes_with_nul().len().try_into()?,
+        )
+    };
+
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("Failed to bind DHCP server to '{}'", interface));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    address: Ipv4Addr,
+    expires: Instant,
+}
+
+pub struct DhcpServer {
+    config: DhcpConfig,
+    leases: HashMap<MacAddr6, Lease>,
+    declined: HashMap<Ipv4Addr, Instant>,
+}
+
+impl DhcpServer {
+    pub fn new(config: DhcpConfig) -> Self {
+        Self {
+            config,
+            leases: HashMap::new(),
+            declined: HashMap::new(),
+        }
+    }
+
+    pub async fn serve(&mut self, socket: &UdpSocket) -> Result<()> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+
+        loop {
+            let (len, _) = socket
+                .recv_from(&mut buf)
+                .await
+                .context("Failed to receive DHCP request")?;
+
+            if let Some((reply, destination)) = self.handle(&buf[..len], Instant::now()) {
+                if let Err(err) = socket.send_to(&reply, destination).await {
+                    println!("Failed to send DHCP reply to {}: {}", destination, err);
+                }
+            }
+        }
+    }
+
+    /// Processes a raw DHCP request and returns the reply together with
+    /// the address it should be sent to.
+    pub fn handle(&mut self, packet: &[u8], now: Instant) -> Option<(Vec<u8>, SocketAddr)> {
+        let request = Request::parse(packet)?;
+
+        // Relayed requests come from other networks than the portal one
+        if request.giaddr != Ipv4Addr::UNSPECIFIED {
+            return None;
+        }
+
+        self.expire(now);
+
+        match request.message_type {
+            DHCPDISCOVER => {
+                let address = self.allocate(&request)?;
+                let expires = self
+                    .leases
+                    .get(&request.chaddr)
+                    .map_or(now + OFFER_TIMEOUT, |lease| {
+                        lease.expires.max(now + OFFER_TIMEOUT)
+                    });
+                self.leases
+                    .insert(request.chaddr, Lease { address, expires });
+                Some(self.reply(&request, DHCPOFFER, address))
+            }
+            DHCPREQUEST => self.handle_request(&request, now),
+            DHCPDECLINE => {
+                if let Some(lease) = self.leases.remove(&request.chaddr) {
+                    println!("DHCP address {} declined", lease.address);
+                    self.declined
+                        .insert(lease.address, now + self.config.lease_time);
+                }
+                None
+            }
+            DHCPRELEASE => {
+                self.leases.remove(&request.chaddr);
+                None
+            }
+            DHCPINFORM => Some(self.reply(&request, DHCPACK, Ipv4Addr::UNSPECIFIED)),
+            _ => None,
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request<'_>,
+        now: Instant,
+    ) -> Option<(Vec<u8>, SocketAddr)> {
+        if let Some(server) = request.server_identifier {
+            if server != self.config.gateway {
+                // The client accepted an offer from another server
+                self.leases.remove(&request.chaddr);
+                return None;
+            }
+        }
+
+        let requested = request.requested_address.unwrap_or(request.ciaddr);
+
+        let acceptable = self.is_in_range(requested)
+            && !self.declined.contains_key(&requested)
+            && self
+                .leases
+                .iter()
+                .all(|(mac, lease)| *mac == request.chaddr || lease.address != requested);
+
+        if !acceptable {
+            self.leases.remove(&request.chaddr);
+            return Some(self.reply(request, DHCPNAK, Ipv4Addr::UNSPECIFIED));
+        }
+
+        self.leases.insert(
+            request.chaddr,
+            Lease {
+                address: requested,
+                expires: now + self.config.lease_time,
+            },
+        );
+
+        println!("DHCP lease {} to {}", requested, request.chaddr);
+
+        Some(self.reply(request, DHCPACK, requested))
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.leases.retain(|_, lease| lease.expires > now);
+        self.declined.retain(|_, expires| *expires > now);
+    }
+
+    fn allocate(&self, request: &Request<'_>) -> Option<Ipv4Addr> {
+        if let Some(lease) = self.leases.get(&request.chaddr) {
+            return Some(lease.address);
+        }
+
+        if let Some(requested) = request.requested_address {
+            if self.is_available(requested) {
+                return Some(requested);
+            }
+        }
+
+        let start = u32::from(self.config.range_start);
+        let end = u32::from(self.config.range_end);
+
+        (start..=end)
+            .map(Ipv4Addr::from)
+            .find(|address| self.is_available(*address))
+    }
+
+    fn is_available(&self, address: Ipv4Addr) -> bool {
+        self.is_in_range(address)
+            && !self.declined.contains_key(&address)
+            && self.leases.values().all(|lease| lease.address != address)
+    }
+
+    fn is_in_range(&self, address: Ipv4Addr) -> bool {
+        address >= self.config.range_start
+            && address <= self.config.range_end
+            && address != self.config.gateway
+    }
+
+    fn reply(
+        &self,
+        request: &Request<'_>,
+        message_type: u8,
+        yiaddr: Ipv4Addr,
+    ) -> (Vec<u8>, SocketAddr) {
+        let mut reply = vec![0; OPTIONS_OFFSET];
+
+        reply[0] = OP_BOOTREPLY;
+        reply[1] = HTYPE_ETHERNET;
+        reply[2] = HLEN_ETHERNET;
+        reply[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(request.xid);
+        reply[FLAGS_OFFSET..FLAGS_OFFSET + 2].copy_from_slice(request.flags);
+        if message_type != DHCPNAK {
+            reply[CIADDR_OFFSET..CIADDR_OFFSET + 4].copy_from_slice(&request.ciaddr.octets());
+        }
+        reply[YIADDR_OFFSET..YIADDR_OFFSET + 4].copy_from_slice(&yiaddr.octets());
+        reply[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(request.chaddr.as_bytes());
+        reply[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE);
+
+        push_option(&mut reply, OPTION_MESSAGE_TYPE, &[message_type]);
+        push_option(
+            &mut reply,
+            OPTION_SERVER_IDENTIFIER,
+            &self.config.gateway.octets(),
+        );
+
+        if message_type != DHCPNAK {
+            if message_type != DHCPACK || yiaddr != Ipv4Addr::UNSPECIFIED {
+                let lease_time =
+                    u32::try_from(self.config.lease_time.as_secs()).unwrap_or(u32::MAX);
+                push_option(&mut reply, OPTION_LEASE_TIME, &lease_time.to_be_bytes());
+                push_option(
+                    &mut reply,
+                    OPTION_RENEWAL_TIME,
+                    &(lease_time / 2).to_be_bytes(),
+                );
+                push_option(
+                    &mut reply,
+                    OPTION_REBINDING_TIME,
+                    &(lease_time / 8 * 7).to_be_bytes(),
+                );
+            }
+            push_option(&mut reply, OPTION_SUBNET_MASK, &SUBNET_MASK.octets());
+            push_option(&mut reply, OPTION_ROUTER, &self.config.gateway.octets());
+            push_option(&mut reply, OPTION_DNS_SERVER, &self.config.gateway.octets());
+        }
+
+        reply.push(OPTION_END);
+
+        if reply.len() < MIN_PACKET_SIZE {
+            reply.resize(MIN_PACKET_SIZE, OPTION_PAD);
+        }
+
+        // Clients that already have an address configured can be reached
+        // directly, everyone else only listens for broadcasts
+        let destination = if message_type == DHCPACK && request.ciaddr != Ipv4Addr::UNSPECIFIED {
+            request.ciaddr
+        } else {
+            Ipv4Addr::BROADCAST
+        };
+
+        (reply, SocketAddr::from((destination, CLIENT_PORT)))
+    }
+}
+
+struct Request<'a> {
+    xid: &'a [u8],
+    flags: &'a [u8],
+    ciaddr: Ipv4Addr,
+    giaddr: Ipv4Addr,
+    chaddr: MacAddr6,
+    message_type: u8,
+    requested_address: Option<Ipv4Addr>,
+    server_identifier: Option<Ipv4Addr>,
+}
+
+impl<'a> Request<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < OPTIONS_OFFSET
+            || packet[0] != OP_BOOTREQUEST
+            || packet[1] != HTYPE_ETHERNET
+            || packet[2] != HLEN_ETHERNET
+            || packet[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut message_type = None;
+        let mut requested_address = None;
+        let mut server_identifier = None;
+
+        let mut options = &packet[OPTIONS_OFFSET..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPTION_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+
+            let (&len, rest) = rest.split_first()?;
+            let data = rest.get(..usize::from(len))?;
+            options = &rest[usize::from(len)..];
+
+            match code {
+                OPTION_MESSAGE_TYPE => message_type = data.first().copied(),
+                OPTION_REQUESTED_ADDRESS => requested_address = read_address(data),
+                OPTION_SERVER_IDENTIFIER => server_identifier = read_address(data),
+                _ => {}
+            }
+        }
+
+        let chaddr: [u8; 6] = packet[CHADDR_OFFSET..CHADDR_OFFSET + 6].try_into().ok()?;
+
+        Some(Self {
+            xid: &packet[XID_OFFSET..XID_OFFSET + 4],
+            flags: &packet[FLAGS_OFFSET..FLAGS_OFFSET + 2],
+            ciaddr: read_address(&packet[CIADDR_OFFSET..CIADDR_OFFSET + 4])?,
+            giaddr: read_address(&packet[GIADDR_OFFSET..GIADDR_OFFSET + 4])?,
+            chaddr: chaddr.into(),
+            message_type: message_type?,
+            requested_address,
+            server_identifier,
+        })
+    }
+}
+
+fn push_option(packet: &mut Vec<u8>, code: u8, data: &[u8]) {
+    packet.push(code);
+    packet.push(u8::try_from(data.len()).unwrap_or(u8::MAX));
+    packet.extend_from_slice(data);
+}
+
+fn read_address(data: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = data.try_into().ok()?;
+    Some(octets.into())
+}
+
+fn is_same_network(address: Ipv4Addr, gateway: Ipv4Addr) -> bool {
+    address.octets()[..3] == gateway.octets()[..3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 42, 1);
+    const OTHER_SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 42, 250);
+
+    const CLIENT_A: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x0a];
+    const CLIENT_B: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x0b];
+    const CLIENT_C: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x0c];
+
+    fn server(range_end: u8) -> DhcpServer {
+        DhcpServer::new(DhcpConfig {
+            gateway: GATEWAY,
+            range_start: Ipv4Addr::new(192, 168, 42, 2),
+            range_end: Ipv4Addr::new(192, 168, 42, range_end),
+            lease_time: Duration::from_secs(600),
+        })
+    }
+
+    fn packet(
+        message_type: u8,
+        chaddr: [u8; 6],
+        requested_address: Option<Ipv4Addr>,
+        server_identifier: Option<Ipv4Addr>,
+    ) -> Vec<u8> {
+        let mut packet = vec![0; OPTIONS_OFFSET];
+        packet[0] = OP_BOOTREQUEST;
+        packet[1] = HTYPE_ETHERNET;
+        packet[2] = HLEN_ETHERNET;
+        packet[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        packet[FLAGS_OFFSET] = 0x80;
+        packet[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(&chaddr);
+        packet[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE);
+
+        push_option(&mut packet, OPTION_MESSAGE_TYPE, &[message_type]);
+        if let Some(address) = requested_address {
+            push_option(&mut packet, OPTION_REQUESTED_ADDRESS, &address.octets());
+        }
+        if let Some(address) = server_identifier {
+            push_option(&mut packet, OPTION_SERVER_IDENTIFIER, &address.octets());
+        }
+        packet.push(OPTION_END);
+
+        packet
+    }
+
+    fn find_option(reply: &[u8], code: u8) -> Option<&[u8]> {
+        let mut options = &reply[OPTIONS_OFFSET..];
+
+        while let Some((&option, rest)) = options.split_first() {
+            match option {
+                OPTION_PAD => options = rest,
+                OPTION_END => return None,
+                _ => {
+                    let len = usize::from(rest[0]);
+                    if option == code {
+                        return Some(&rest[1..=len]);
+                    }
+                    options = &rest[1 + len..];
+                }
+            }
+        }
+
+        None
+    }
+
+    fn message_type(reply: &[u8]) -> u8 {
+        find_option(reply, OPTION_MESSAGE_TYPE).unwrap()[0]
+    }
+
+    fn yiaddr(reply: &[u8]) -> Ipv4Addr {
+        read_address(&reply[YIADDR_OFFSET..YIADDR_OFFSET + 4]).unwrap()
+    }
+
+    #[test]
+    fn offers_first_free_address() {
+        let mut server = server(254);
+        let now = Instant::now();
+
+        let (reply, destination) = server
+            .handle(&packet(DHCPDISCOVER, CLIENT_A, None, None), now)
+            .unwrap();
+
+        assert_eq!(
+            destination,
+            SocketAddr::from((Ipv4Addr::BROADCAST, CLIENT_PORT))
+        );
+        assert_eq!(reply[0], OP_BOOTREPLY);
+        assert_eq!(
+            &reply[XID_OFFSET..XID_OFFSET + 4],
+            &[0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(reply[FLAGS_OFFSET], 0x80);
+        assert_eq!(&reply[CHADDR_OFFSET..CHADDR_OFFSET + 6], &CLIENT_A);
+        assert!(reply.len() >= MIN_PACKET_SIZE);
+
+        assert_eq!(message_type(&reply), DHCPOFFER);
+        assert_eq!(yiaddr(&reply), Ipv4Addr::new(192, 168, 42, 2));
+        assert_eq!(
+            find_option(&reply, OPTION_SERVER_IDENTIFIER),
+            Some(&GATEWAY.octets()[..])
+        );
+        assert_eq!(
+            find_option(&reply, OPTION_ROUTER),
+            Some(&GATEWAY.octets()[..])
+        );
+        assert_eq!(
+            find_option(&reply, OPTION_DNS_SERVER),
+            Some(&GATEWAY.octets()[..])
+        );
+        assert_eq!(
+            find_option(&reply, OPTION_SUBNET_MASK),
+            Some(&SUBNET_MASK.octets()[..])
+        );
+        assert_eq!(
+            find_option(&reply, OPTION_LEASE_TIME),
+            Some(&600_u32.to_be_bytes()[..])
+        );
+    }
+
+    #[test]
+    fn offers_requested_address_when_free() {
+        let mut server = server(254);
+        let requested = Ipv4Addr::new(192, 168, 42, 77);
+
+        let (reply, _) = server
+            .handle(
+                &packet(DHCPDISCOVER, CLIENT_A, Some(requested), None),
+                Instant::now(),
+            )
+            .unwrap();
+
+        assert_eq!(yiaddr(&reply), requested);
+    }
+
+    #[test]
+    fn acknowledges_request_for_offered_address() {
+        let mut server = server(254);
+        let now = Instant::now();
+
+        let (offer, _) = server
+            .handle(&packet(DHCPDISCOVER, CLIENT_A, None, None), now)
+            .unwrap();
+        let offered = yiaddr(&offer);
+
+        let (reply, _) = server
+            .handle(
+                &packet(DHCPREQUEST, CLIENT_A, Some(offered), Some(GATEWAY)),
+                now,
+            )
+            .unwrap();
+
+        assert_eq!(message_type(&reply), DHCPACK);
+        assert_eq!(yiaddr(&reply), offered);
+    }
+
+    #[test]
+    fn rejects_request_for_address_of_another_client() {
+        let mut server = server(254);
+        let now = Instant::now();
+
+        let (offer, _) = server
+            .handle(&packet(DHCPDISCOVER, CLIENT_A, None, None), now)
+            .unwrap();
+        let taken = yiaddr(&offer);
+
+        let (reply, _) = server
+            .handle(&packet(DHCPREQUEST, CLIENT_B, Some(taken), None), now)
+            .unwrap();
+
+        assert_eq!(message_type(&reply), DHCPNAK);
+        assert_eq!(yiaddr(&reply), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(find_option(&reply, OPTION_LEASE_TIME), None);
+    }
+
+    #[test]
+    fn rejects_request_outside_of_range() {
+        let mut server = server(254);
+
+        let (reply, _) = server
+            .handle(
+                &packet(
+                    DHCPREQUEST,
+                    CLIENT_A,
+                    Some(Ipv4Addr::new(10, 0, 0, 5)),
+                    None,
+                ),
+                Instant::now(),
+            )
+            .unwrap();
+
+        assert_eq!(message_type(&reply), DHCPNAK);
+    }
+
+    #[test]
+    fn ignores_request_for_another_server() {
+        let mut server = server(254);
+        let now = Instant::now();
+
+        server
+            .handle(&packet(DHCPDISCOVER, CLIENT_A, None, None), now)
+            .unwrap();
+
+        let reply = server.handle(
+            &packet(
+                DHCPREQUEST,
+                CLIENT_A,
+                Some(Ipv4Addr::new(192, 168, 42, 2)),
+                Some(OTHER_SERVER),
+            ),
+            now,
+        );
+
+        assert!(reply.is_none());
+        assert!(server.leases.is_empty());
+    }
+
+    #[test]
+    fn offers_nothing_when_range_is_exhausted() {
+        let mut server = server(3);
+        let now = Instant::now();
+
+        for client in [CLIENT_A, CLIENT_B] {
+            server
+                .handle(&packet(DHCPDISCOVER, client, None, None), now)
+                .unwrap();
+        }
+
+        assert!(server
+            .handle(&packet(DHCPDISCOVER, CLIENT_C, None, None), now)
+            .is_none());
+
+        // Offers that were not taken up expire and free their address
+        let (reply, _) = server
+            .handle(
+                &packet(DHCPDISCOVER, CLIENT_C, None, None),
+                now + OFFER_TIMEOUT + Duration::from_secs(1),
+            )
+            .unwrap();
+
+        assert_eq!(message_type(&reply), DHCPOFFER);
+    }
+
+    #[test]
+    fn reuses_lease_of_same_client() {
+        let mut server = server(254);
+        let now = Instant::now();
+
+        let (offer, _) = server
+            .handle(&packet(DHCPDISCOVER, CLIENT_A, None, None), now)
+            .unwrap();
+        let leased = yiaddr(&offer);
+        server
+            .handle(
+                &packet(DHCPREQUEST, CLIENT_A, Some(leased), Some(GATEWAY)),
+                now,
+            )
+            .unwrap();
+
+        server
+            .handle(&packet(DHCPDISCOVER, CLIENT_B, None, None), now)
+            .unwrap();
+
+        let (reply, _) = server
+            .handle(
+                &packet(DHCPDISCOVER, CLIENT_A, None, None),
+                now + Duration::from_secs(300),
+            )
+            .unwrap();
+
+        assert_eq!(yiaddr(&reply), leased);
+    }
+
+    #[test]
+    fn ignores_truncated_packets() {
+        let mut server = server(254);
+        let packet = packet(DHCPDISCOVER, CLIENT_A, None, None);
+
+        // Fixed header cut short
+        assert!(server
+            .handle(&packet[..OPTIONS_OFFSET - 1], Instant::now())
+            .is_none());
+
+        // Option length running past the end of the packet
+        let mut truncated_option = packet[..OPTIONS_OFFSET].to_vec();
+        truncated_option.extend_from_slice(&[OPTION_MESSAGE_TYPE, 4, DHCPDISCOVER]);
+        assert!(server.handle(&truncated_option, Instant::now()).is_none());
+
+        // No message type at all
+        let mut no_message_type = packet[..OPTIONS_OFFSET].to_vec();
+        no_message_type.push(OPTION_END);
+        assert!(server.handle(&no_message_type, Instant::now()).is_none());
+
+        assert!(server.leases.is_empty());
+    }
+}
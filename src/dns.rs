@@ -0,0 +1,328 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+use crate::network::{wait_for_portal_active, PortalState};
+
+const DNS_PORT: u16 = 53;
+const MAX_PACKET_SIZE: usize = 512;
+const HEADER_SIZE: usize = 12;
+
+/// The gateway address is assigned by NetworkManager only after the portal
+/// comes up, so binding is retried
+const BIND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// Keep the TTL short so that clients do not keep resolving everything to
+// the gateway once the device has been provisioned
+const ANSWER_TTL: u32 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+
+const RCODE_FORMAT_ERROR: u16 = 1;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+// Compression pointer to the question name right after the 12 byte header
+const NAME_POINTER: u16 = 0xC00C;
+
+/// Runs the captive portal DNS responder for as long as the portal is
+/// active, binding to the gateway address whenever the portal comes up.
+pub async fn run_dns_server(gateway: Ipv4Addr, mut portal_state: watch::Receiver<PortalState>) {
+    let mut bind_failed = false;
+
+    loop {
+        if !wait_for_portal_active(&mut portal_state, true).await {
+            return;
+        }
+
+        let address = SocketAddr::from((gateway, DNS_PORT));
+
+        match UdpSocket::bind(address).await {
+            Ok(socket) => {
+                bind_failed = false;
+
+                println!("DNS server listening on {}", address);
+
+                tokio::select! {
+                    result = serve(&socket, gateway) => {
+                        if let Err(err) = result {
+                            println!("DNS server failed: {:#}", err);
+                        }
+                    },
+                    _ = wait_for_portal_active(&mut portal_state, false) => {},
+                }
+
+                println!("DNS server stopped");
+            }
+            Err(err) => {
+                if !bind_failed {
+                    println!(
+                        "Failed to bind DNS server to {}, retrying: {}",
+                        address, err
+                    );
+                    bind_failed = true;
+                }
+
+                // Gives up once the portal goes down, until it comes up again
+                tokio::time::sleep(BIND_RETRY_DELAY).await;
+                continue;
+            }
+        }
+
+        if !wait_for_portal_active(&mut portal_state, false).await {
+            return;
+        }
+    }
+}
+
+/// Answers every query received on `socket` so that all names resolve to
+/// `address`.
+pub async fn serve(socket: &UdpSocket, address: Ipv4Addr) -> Result<()> {
+    let mut buf = [0; MAX_PACKET_SIZE];
+
+    loop {
+        let (len, peer) = socket
+            .recv_from(&mut buf)
+            .await
+            .context("Failed to receive DNS query")?;
+
+        if let Some(response) = build_response(&buf[..len], address) {
+            if let Err(err) = socket.send_to(&response, peer).await {
+                println!("Failed to send DNS response to {}: {}", peer, err);
+            }
+        }
+    }
+}
+
+/// Builds the response to a raw DNS query. A queries are answered with
+/// `address`, all other query types get an empty answer section.
+pub fn build_response(query: &[u8], address: Ipv4Addr) -> Option<Vec<u8>> {
+    if query.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let id = read_u16(query, 0)?;
+    let flags = read_u16(query, 2)?;
+    let question_count = read_u16(query, 4)?;
+
+    if flags & FLAG_RESPONSE != 0 {
+        return None;
+    }
+
+    if flags & OPCODE_MASK != 0 {
+        return Some(error_response(id, flags, RCODE_NOT_IMPLEMENTED));
+    }
+
+    if question_count == 0 {
+        return Some(error_response(id, flags, RCODE_FORMAT_ERROR));
+    }
+
+    let question_end = match question_end(query) {
+        Some(end) => end,
+        None => return Some(error_response(id, flags, RCODE_FORMAT_ERROR)),
+    };
+
+    let qtype = read_u16(query, question_end - 4)?;
+    let qclass = read_u16(query, question_end - 2)?;
+
+    let answer = (qclass == CLASS_IN && (qtype == TYPE_A || qtype == TYPE_ANY)).then(|| address);
+
+    let mut response = Vec::with_capacity(question_end + 16);
+    write_header(
+        &mut response,
+        id,
+        response_flags(flags, 0),
+        1,
+        u16::from(answer.is_some()),
+    );
+    response.extend_from_slice(&query[HEADER_SIZE..question_end]);
+
+    if let Some(address) = answer {
+        response.extend_from_slice(&NAME_POINTER.to_be_bytes());
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+        response.extend_from_slice(&4_u16.to_be_bytes());
+        response.extend_from_slice(&address.octets());
+    }
+
+    Some(response)
+}
+
+/// Returns the offset right after the first question, i.e. after its
+/// name, type and class.
+fn question_end(query: &[u8]) -> Option<usize> {
+    let mut offset = HEADER_SIZE;
+
+    loop {
+        let len = usize::from(*query.get(offset)?);
+        offset += 1;
+
+        if len == 0 {
+            break;
+        }
+
+        // Compression pointers and extended label types are not valid in
+        // the question of a query
+        if len > 63 {
+            return None;
+        }
+
+        offset += len;
+    }
+
+    let end = offset + 4;
+
+    (end <= query.len()).then(|| end)
+}
+
+fn error_response(id: u16, flags: u16, rcode: u16) -> Vec<u8> {
+    let mut response = Vec::with_capacity(HEADER_SIZE);
+    write_header(&mut response, id, response_flags(flags, rcode), 0, 0);
+    response
+}
+
+fn response_flags(query_flags: u16, rcode: u16) -> u16 {
+    FLAG_RESPONSE
+        | FLAG_AUTHORITATIVE
+        | (query_flags & (OPCODE_MASK | FLAG_RECURSION_DESIRED))
+        | rcode
+}
+
+fn write_header(
+    response: &mut Vec<u8>,
+    id: u16,
+    flags: u16,
+    question_count: u16,
+    answer_count: u16,
+) {
+    response.extend_from_slice(&id.to_be_bytes());
+    response.extend_from_slice(&flags.to_be_bytes());
+    response.extend_from_slice(&question_count.to_be_bytes());
+    response.extend_from_slice(&answer_count.to_be_bytes());
+    response.extend_from_slice(&0_u16.to_be_bytes());
+    response.extend_from_slice(&0_u16.to_be_bytes());
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 42, 1);
+
+    const TYPE_AAAA: u16 = 28;
+
+    /// Query for `example.com` with recursion desired.
+    fn query(id: u16, qtype: u16) -> Vec<u8> {
+        let mut query = Vec::new();
+        write_header(&mut query, id, FLAG_RECURSION_DESIRED, 1, 0);
+        query.extend_from_slice(b"\x07example\x03com\x00");
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    /// Sends `query` to a responder on the loopback interface and returns
+    /// the response, if any arrives.
+    async fn exchange(query: &[u8]) -> Option<Vec<u8>> {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        let server = tokio::spawn(async move { serve(&server, GATEWAY).await });
+
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        client.send_to(query, server_address).await.unwrap();
+
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let received =
+            tokio::time::timeout(Duration::from_millis(200), client.recv(&mut buf)).await;
+
+        server.abort();
+
+        received.ok().map(|len| buf[..len.unwrap()].to_vec())
+    }
+
+    #[tokio::test]
+    async fn answers_a_query_with_gateway() {
+        let query = query(0x1234, TYPE_A);
+        let response = exchange(&query).await.unwrap();
+
+        assert_eq!(read_u16(&response, 0), Some(0x1234));
+        assert_eq!(
+            read_u16(&response, 2),
+            Some(FLAG_RESPONSE | FLAG_AUTHORITATIVE | FLAG_RECURSION_DESIRED)
+        );
+        assert_eq!(read_u16(&response, 4), Some(1));
+        assert_eq!(read_u16(&response, 6), Some(1));
+
+        // The question is echoed back, followed by the answer
+        assert_eq!(&response[HEADER_SIZE..query.len()], &query[HEADER_SIZE..]);
+
+        let answer = &response[query.len()..];
+        assert_eq!(read_u16(answer, 0), Some(NAME_POINTER));
+        assert_eq!(read_u16(answer, 2), Some(TYPE_A));
+        assert_eq!(read_u16(answer, 4), Some(CLASS_IN));
+        assert_eq!(&answer[6..10], &ANSWER_TTL.to_be_bytes());
+        assert_eq!(read_u16(answer, 10), Some(4));
+        assert_eq!(&answer[12..], &GATEWAY.octets());
+    }
+
+    #[tokio::test]
+    async fn answers_aaaa_query_without_records() {
+        let query = query(0xbeef, TYPE_AAAA);
+        let response = exchange(&query).await.unwrap();
+
+        assert_eq!(read_u16(&response, 0), Some(0xbeef));
+        assert_eq!(
+            read_u16(&response, 2),
+            Some(FLAG_RESPONSE | FLAG_AUTHORITATIVE | FLAG_RECURSION_DESIRED)
+        );
+        assert_eq!(read_u16(&response, 4), Some(1));
+        assert_eq!(read_u16(&response, 6), Some(0));
+        assert_eq!(response.len(), query.len());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_question_with_format_error() {
+        let query = query(0x4242, TYPE_A);
+        let response = exchange(&query[..query.len() - 3]).await.unwrap();
+
+        assert_eq!(read_u16(&response, 0), Some(0x4242));
+        assert_eq!(
+            read_u16(&response, 2),
+            Some(FLAG_RESPONSE | FLAG_AUTHORITATIVE | FLAG_RECURSION_DESIRED | RCODE_FORMAT_ERROR)
+        );
+        assert_eq!(response.len(), HEADER_SIZE);
+    }
+
+    #[tokio::test]
+    async fn ignores_truncated_header() {
+        let query = query(0x4242, TYPE_A);
+
+        assert_eq!(exchange(&query[..HEADER_SIZE - 1]).await, None);
+    }
+
+    #[tokio::test]
+    async fn ignores_responses() {
+        let mut query = query(0x4242, TYPE_A);
+        query[2] |= 0x80;
+
+        assert_eq!(exchange(&query).await, None);
+    }
+}
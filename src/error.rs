@@ -0,0 +1,46 @@
+use std::fmt;
+
+/// A request the client got wrong, e.g. a missing password or a malformed
+/// parameter, reported with `400 Bad Request` instead of as a server error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRequest(String);
+
+impl InvalidRequest {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl fmt::Display for InvalidRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidRequest {}
+
+/// NetworkManager gave up on the requested network, e.g. because of a wrong
+/// password, reported with `502 Bad Gateway`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionFailed {
+    pub ssid: String,
+    /// Final state of the connection attempt
+    pub state: String,
+}
+
+impl ConnectionFailed {
+    pub fn new(ssid: impl Into<String>, state: impl Into<String>) -> Self {
+        Self {
+            ssid: ssid.into(),
+            state: state.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not connect to '{}' ({})", self.ssid, self.state)
+    }
+}
+
+impl std::error::Error for ConnectionFailed {}
@@ -19,45 +19,124 @@
     clippy::mod_module_files
 )]
 
+mod activity;
+mod captive;
+mod dhcp;
+mod dns;
+mod error;
 mod network;
 mod nl80211;
 mod opts;
+mod pages;
+mod ui;
 mod web;
 
+use std::net::Ipv4Addr;
+use std::process::ExitCode;
 use std::thread;
 
 use anyhow::{Context, Result};
 
 use clap::Parser;
 
-use tokio::sync::oneshot;
+use tokio::runtime::Handle;
+use tokio::sync::{oneshot, watch};
 
-use crate::network::{create_channel, run_network_manager_loop};
-use crate::opts::Opts;
-use crate::web::run_web_loop;
+use crate::dhcp::{run_dhcp_server, DhcpConfig};
+use crate::dns::run_dns_server;
+use crate::network::{create_channel, run_network_manager_loop, NetworkInfo, PortalState};
+use crate::nl80211::session::Nl80211Session;
+use crate::opts::{DhcpMode, Opts};
+use crate::web::{run_web_loop, ExitReason};
+
+/// Lets a supervisor tell an unattended timeout apart from other exits.
+const EXIT_CODE_ACTIVITY_TIMEOUT: u8 = 3;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let opts: Opts = Opts::parse();
 
+    let gateway: Ipv4Addr = opts
+        .gateway
+        .parse()
+        .context("Failed to parse gateway address")?;
+
+    let web_opts = opts.clone();
+
+    let dhcp_mode = opts.dhcp;
+    // The range and lease options only apply to the builtin server
+    let dhcp_config = if dhcp_mode == DhcpMode::Builtin {
+        Some(DhcpConfig::from_opts(&opts, gateway).context("Invalid DHCP configuration")?)
+    } else {
+        None
+    };
+
     let (glib_sender, glib_receiver) = create_channel();
 
     let (initialized_sender, initialized_receiver) = oneshot::channel();
 
+    let (portal_state_sender, portal_state_receiver) = watch::channel(PortalState::Inactive);
+
+    let runtime = Handle::current();
+
+    // The portal itself only needs NetworkManager, nl80211 adds scanning and
+    // the radio details
+    let session = match Nl80211Session::new() {
+        Ok(session) => Some(session),
+        Err(err) => {
+            println!("Failed to connect to nl80211: {:#}", err);
+            None
+        }
+    };
+    let network_session = session.clone();
+
     thread::spawn(move || {
-        run_network_manager_loop(opts, initialized_sender, glib_receiver);
+        run_network_manager_loop(
+            opts,
+            runtime,
+            network_session,
+            portal_state_sender,
+            initialized_sender,
+            glib_receiver,
+        );
     });
 
-    receive_network_initialized(initialized_receiver).await?;
+    let network_info = receive_network_initialized(initialized_receiver).await?;
+
+    // In shared mode the dnsmasq instance of NetworkManager holds the DNS
+    // port on the gateway. It forwards queries upstream instead of resolving
+    // every name to the gateway, so there is no captive DNS in that mode.
+    if dhcp_mode != DhcpMode::Shared {
+        tokio::spawn(run_dns_server(gateway, portal_state_receiver.clone()));
+    }
+
+    if let Some(dhcp_config) = dhcp_config {
+        tokio::spawn(run_dhcp_server(
+            dhcp_config,
+            network_info.portal_interface.clone(),
+            portal_state_receiver.clone(),
+        ));
+    }
 
-    run_web_loop(glib_sender).await;
+    let exit_reason = run_web_loop(
+        web_opts,
+        gateway,
+        network_info,
+        session,
+        glib_sender,
+        portal_state_receiver,
+    )
+    .await?;
 
-    Ok(())
+    match exit_reason {
+        ExitReason::Shutdown => Ok(ExitCode::SUCCESS),
+        ExitReason::ActivityTimeout => Ok(ExitCode::from(EXIT_CODE_ACTIVITY_TIMEOUT)),
+    }
 }
 
 async fn receive_network_initialized(
-    initialized_receiver: oneshot::Receiver<Result<()>>,
-) -> Result<()> {
+    initialized_receiver: oneshot::Receiver<Result<NetworkInfo>>,
+) -> Result<NetworkInfo> {
     let received = initialized_receiver
         .await
         .context("Failed to receive network initialization response");
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, bail, Context, Result};
 
-use tokio::sync::oneshot;
+use tokio::runtime::Handle;
+use tokio::sync::{oneshot, watch};
 
 use glib::translate::FromGlib;
 use glib::{MainContext, MainLoop};
@@ -9,21 +10,30 @@ use std::cell::RefCell;
 use std::collections::HashSet;
 use std::future::Future;
 use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::Serialize;
 
-use crate::opts::Opts;
+use crate::error::{ConnectionFailed, InvalidRequest};
+use crate::nl80211;
+use crate::nl80211::bss::Band;
+use crate::nl80211::session::{Nl80211Session, NL80211_NOT_AVAILABLE};
+use crate::opts::{ApChannel, DhcpMode, Opts, StartWhen};
 
 use nm::{
-    utils_get_timestamp_msec, AccessPoint, ActiveConnection, ActiveConnectionExt,
-    ActiveConnectionState, Cast, Client, Connection, ConnectionExt, Device, DeviceExt, DeviceState,
-    DeviceType, DeviceWifi, IPAddress, SettingConnection, SettingIP4Config, SettingIPConfigExt,
-    SettingWireless, SettingWirelessSecurity, SimpleConnection, SETTING_IP4_CONFIG_METHOD_MANUAL,
-    SETTING_WIRELESS_MODE_AP, SETTING_WIRELESS_SETTING_NAME,
+    _80211ApFlags, _80211ApSecurityFlags, utils_get_timestamp_msec, utils_wifi_freq_to_channel,
+    AccessPoint, ActiveConnection, ActiveConnectionExt, ActiveConnectionState, Cast, Client,
+    Connection, ConnectionExt, ConnectivityState, Device, DeviceExt, DeviceState, DeviceType,
+    DeviceWifi, IPAddress, Setting8021x, SettingConnection, SettingIP4Config, SettingIPConfigExt,
+    SettingWireless, SettingWirelessSecurity, SimpleConnection, WepKeyType,
+    SETTING_IP4_CONFIG_METHOD_MANUAL, SETTING_IP4_CONFIG_METHOD_SHARED, SETTING_WIRELESS_MODE_AP,
+    SETTING_WIRELESS_MODE_INFRA, SETTING_WIRELESS_SETTING_NAME,
 };
 
 const WIFI_SCAN_TIMEOUT_SECONDS: usize = 45;
 
+const VIRTUAL_INTERFACE_TIMEOUT_SECONDS: usize = 10;
+
 const NETWORK_THREAD_NOT_INITIALIZED: &str = "Network thread not yet initialized";
 
 type TokioResponder = oneshot::Sender<Result<CommandResponce>>;
@@ -33,10 +43,42 @@ pub enum Command {
     CheckConnectivity,
     ListConnections,
     ListWiFiNetworks,
+    Rescan {
+        ssids: Vec<String>,
+    },
+    Connect {
+        ssid: String,
+        passphrase: Option<String>,
+        identity: Option<String>,
+        hidden: bool,
+    },
     Shutdown,
     Stop,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalState {
+    Inactive,
+    Active,
+    Provisioned,
+}
+
+pub struct NetworkInfo {
+    pub interface: String,
+    /// Interface running the portal access point, differs from `interface`
+    /// when a virtual access point interface is used
+    pub portal_interface: String,
+}
+
+impl NetworkInfo {
+    fn new(interface: String, portal_interface: String) -> Self {
+        Self {
+            interface,
+            portal_interface,
+        }
+    }
+}
+
 pub struct CommandRequest {
     responder: TokioResponder,
     command: Command,
@@ -52,6 +94,8 @@ pub enum CommandResponce {
     CheckConnectivity(Connectivity),
     ListConnections(ConnectionList),
     ListWiFiNetworks(NetworkList),
+    Rescan(NetworkList),
+    Connect(Connect),
     Shutdown(Shutdown),
     Stop(Stop),
 }
@@ -90,14 +134,25 @@ impl ConnectionDetails {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct NetworkList {
     pub stations: Vec<Station>,
+    /// Channels with access points that do not broadcast their SSID
+    pub hidden_channels: Vec<u32>,
+    /// Milliseconds since the Unix epoch when the stations were scanned
+    pub scanned_at: u64,
 }
 
 impl NetworkList {
-    fn new(stations: Vec<Station>) -> Self {
-        Self { stations }
+    fn new(stations: Vec<Station>, mut hidden_channels: Vec<u32>) -> Self {
+        hidden_channels.sort_unstable();
+        hidden_channels.dedup();
+
+        Self {
+            stations,
+            hidden_channels,
+            scanned_at: timestamp_msec(),
+        }
     }
 }
 
@@ -105,11 +160,101 @@ impl NetworkList {
 pub struct Station {
     pub ssid: String,
     pub quality: u8,
+    pub security: Security,
 }
 
 impl Station {
-    fn new(ssid: String, quality: u8) -> Self {
-        Self { ssid, quality }
+    fn new(ssid: String, quality: u8, security: Security) -> Self {
+        Self {
+            ssid,
+            quality,
+            security,
+        }
+    }
+}
+
+/// Security of a network as advertised by its access points. Networks in
+/// WPA2/WPA3 transition mode are reported as WPA2, as they accept both.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2,
+    Wpa3,
+    Owe,
+    Enterprise,
+}
+
+impl Security {
+    fn from_access_point(ap: &AccessPoint) -> Self {
+        let wpa_flags = ap.wpa_flags();
+        let rsn_flags = ap.rsn_flags();
+        let key_mgmt = wpa_flags | rsn_flags;
+
+        if key_mgmt.intersects(
+            _80211ApSecurityFlags::KEY_MGMT_802_1X
+                | _80211ApSecurityFlags::KEY_MGMT_EAP_SUITE_B_192,
+        ) {
+            Self::Enterprise
+        } else if rsn_flags.contains(_80211ApSecurityFlags::KEY_MGMT_SAE)
+            && !rsn_flags.contains(_80211ApSecurityFlags::KEY_MGMT_PSK)
+        {
+            Self::Wpa3
+        } else if rsn_flags.contains(_80211ApSecurityFlags::KEY_MGMT_OWE) {
+            Self::Owe
+        } else if !rsn_flags.is_empty() {
+            Self::Wpa2
+        } else if !wpa_flags.is_empty() {
+            Self::Wpa
+        } else if ap.flags().contains(_80211ApFlags::PRIVACY) {
+            Self::Wep
+        } else {
+            Self::Open
+        }
+    }
+
+    fn validate(
+        self,
+        ssid: &str,
+        passphrase: Option<&str>,
+        identity: Option<&str>,
+    ) -> Result<(), InvalidRequest> {
+        if self != Self::Enterprise && identity.is_some() {
+            return Err(InvalidRequest::new(format!(
+                "Network '{}' does not use a username",
+                ssid
+            )));
+        }
+
+        let message = match self {
+            Self::Open | Self::Owe if passphrase.is_some() => "does not use a password",
+            Self::Wep | Self::Wpa | Self::Wpa2 | Self::Wpa3 if passphrase.is_none() => {
+                "requires a password"
+            }
+            Self::Enterprise if passphrase.is_none() || identity.is_none() => {
+                "requires a username and a password"
+            }
+            _ => return Ok(()),
+        };
+
+        Err(InvalidRequest::new(format!(
+            "Network '{}' {}",
+            ssid, message
+        )))
+    }
+}
+
+#[derive(Serialize)]
+pub struct Connect {
+    pub ssid: String,
+    pub state: String,
+}
+
+impl Connect {
+    fn new(ssid: String, state: String) -> Self {
+        Self { ssid, state }
     }
 }
 
@@ -137,27 +282,69 @@ impl Stop {
 
 struct NetworkState {
     client: Client,
-    _device: DeviceWifi,
-    stations: Vec<Station>,
+    device: DeviceWifi,
+    networks: NetworkList,
     portal_connection: Option<ActiveConnection>,
+    portal_state: watch::Sender<PortalState>,
+    /// Connection attempts through the API that have not finished yet
+    connecting: usize,
+    runtime: Handle,
+    session: Option<Nl80211Session>,
+    opts: Opts,
 }
 
 impl NetworkState {
     fn new(
         client: Client,
-        _device: DeviceWifi,
-        stations: Vec<Station>,
+        device: DeviceWifi,
+        networks: NetworkList,
         portal_connection: Option<ActiveConnection>,
+        portal_state: watch::Sender<PortalState>,
+        runtime: Handle,
+        session: Option<Nl80211Session>,
+        opts: Opts,
     ) -> Self {
         Self {
             client,
-            _device,
-            stations,
+            device,
+            networks,
             portal_connection,
+            portal_state,
+            connecting: 0,
+            runtime,
+            session,
+            opts,
         }
     }
 }
 
+/// Marks a connection attempt as in flight for as long as it is alive, so
+/// that the portal is not started on the device while it connects.
+struct ConnectingGuard;
+
+impl ConnectingGuard {
+    fn new() -> Result<Self> {
+        GLOBAL.with(|global| {
+            if let Some(ref mut state) = *global.borrow_mut() {
+                state.connecting += 1;
+                Ok(Self)
+            } else {
+                Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+            }
+        })
+    }
+}
+
+impl Drop for ConnectingGuard {
+    fn drop(&mut self) {
+        GLOBAL.with(|global| {
+            if let Some(ref mut state) = *global.borrow_mut() {
+                state.connecting -= 1;
+            }
+        });
+    }
+}
+
 thread_local! {
     static GLOBAL: RefCell<Option<NetworkState>> = RefCell::new(None);
 }
@@ -168,7 +355,10 @@ pub fn create_channel() -> (glib::Sender<CommandRequest>, glib::Receiver<Command
 
 pub fn run_network_manager_loop(
     opts: Opts,
-    initialized_sender: oneshot::Sender<Result<()>>,
+    runtime: Handle,
+    session: Option<Nl80211Session>,
+    portal_state: watch::Sender<PortalState>,
+    initialized_sender: oneshot::Sender<Result<NetworkInfo>>,
     glib_receiver: glib::Receiver<CommandRequest>,
 ) {
     let context = MainContext::new();
@@ -178,20 +368,37 @@ pub fn run_network_manager_loop(
         .with_thread_default(|| {
             glib_receiver.attach(None, dispatch_command_requests);
 
-            context.spawn_local(init_network_respond(opts, initialized_sender));
+            context.spawn_local(init_network_respond(
+                opts,
+                runtime,
+                session,
+                portal_state,
+                initialized_sender,
+            ));
 
             loop_.run();
         })
         .unwrap();
 }
 
-async fn init_network_respond(opts: Opts, initialized_sender: oneshot::Sender<Result<()>>) {
-    let init_result = init_network(opts).await;
+async fn init_network_respond(
+    opts: Opts,
+    runtime: Handle,
+    session: Option<Nl80211Session>,
+    portal_state: watch::Sender<PortalState>,
+    initialized_sender: oneshot::Sender<Result<NetworkInfo>>,
+) {
+    let init_result = init_network(opts, runtime, session, portal_state).await;
 
     initialized_sender.send(init_result).ok();
 }
 
-async fn init_network(opts: Opts) -> Result<()> {
+async fn init_network(
+    opts: Opts,
+    runtime: Handle,
+    session: Option<Nl80211Session>,
+    portal_state: watch::Sender<PortalState>,
+) -> Result<NetworkInfo> {
     let client = create_client().await?;
 
     delete_exising_wifi_connect_ap_profile(&client, &opts.ssid).await?;
@@ -202,37 +409,224 @@ async fn init_network(opts: Opts) -> Result<()> {
 
     println!("Interface: {}", interface);
 
+    check_wiphy(session.as_ref(), interface.as_str(), &opts).await?;
+
     scan_wifi(&device).await?;
 
     let access_points = get_nearby_access_points(&device);
 
     let stations = access_points
         .iter()
-        .map(|ap| Station::new(ap_ssid(ap), ap.strength()))
+        .map(|ap| Station::new(ap_ssid(ap), ap.strength(), Security::from_access_point(ap)))
         .collect::<Vec<_>>();
 
-    let portal_connection = Some(
-        create_portal(&client, &device, &opts)
+    let networks = NetworkList::new(stations, get_hidden_channels(&device));
+
+    let start_portal = should_start_portal(&client, &opts).await?;
+
+    // NetworkManager may still be connecting, so the portal is started
+    // later, with the web server already serving
+    let wait_for_connectivity = start_portal && opts.start_when == StartWhen::NoConnectivity;
+
+    let portal_connection = if start_portal && !wait_for_connectivity {
+        let portal_connection = create_portal(&client, &device, session.as_ref(), &opts)
             .await
-            .context("Failed to create captive portal")?,
-    );
+            .context("Failed to create captive portal")?;
+
+        portal_state.send_replace(PortalState::Active);
+
+        Some(portal_connection)
+    } else {
+        None
+    };
+
+    let portal_interface = opts
+        .ap_interface
+        .clone()
+        .unwrap_or_else(|| interface.to_string());
 
     GLOBAL.with(|global| {
-        let state = NetworkState::new(client, device, stations, portal_connection);
+        let state = NetworkState::new(
+            client,
+            device,
+            networks,
+            portal_connection,
+            portal_state,
+            runtime,
+            session,
+            opts,
+        );
         *global.borrow_mut() = Some(state);
     });
 
+    if wait_for_connectivity {
+        spawn_local(start_portal_after_grace_period());
+    }
+
     println!("Network initilized");
 
+    Ok(NetworkInfo::new(interface.to_string(), portal_interface))
+}
+
+/// Fails early if the radio cannot run an access point, or cannot run it next
+/// to a station when a virtual access point interface is requested. Drivers
+/// without nl80211 support are given the benefit of the doubt, unless the
+/// virtual access point interface has to be created through it.
+async fn check_wiphy(session: Option<&Nl80211Session>, interface: &str, opts: &Opts) -> Result<()> {
+    let session = match session {
+        Some(session) => session,
+        None if opts.ap_interface.is_some() => {
+            bail!("A virtual access point interface requires nl80211")
+        }
+        None => return Ok(()),
+    };
+
+    let wiphy = match nl80211::wiphy::get_wiphy(session, interface).await {
+        Ok(wiphy) => wiphy,
+        Err(err) => {
+            println!("Failed to query WiFi capabilities: {:?}", err);
+            return Ok(());
+        }
+    };
+
+    if !wiphy.supports_ap() {
+        bail!(
+            "WiFi interface '{}' ({}) does not support access point mode",
+            interface,
+            wiphy.name
+        );
+    }
+
+    if opts.ap_interface.is_some() && !wiphy.supports_ap_and_station() {
+        bail!(
+            "WiFi interface '{}' ({}) cannot run an access point and a station at the same time",
+            interface,
+            wiphy.name
+        );
+    }
+
+    if let Some(ApChannel::Fixed(channel)) = opts.ap_channel {
+        let available = wiphy
+            .ap_frequencies(channel_band(channel))
+            .iter()
+            .any(|frequency| frequency.channel == Some(channel));
+
+        if !available {
+            bail!(
+                "Channel {} is not available for an access point on '{}' ({})",
+                channel,
+                interface,
+                wiphy.name
+            );
+        }
+    }
+
+    let bands: Vec<_> = [Band::Band2GHz, Band::Band5GHz, Band::Band6GHz]
+        .into_iter()
+        .filter(|band| wiphy.supports_band(*band))
+        .map(|band| band.to_string())
+        .collect();
+
+    println!(
+        "Radio {}: bands {}, concurrent access point and station: {}",
+        wiphy.name,
+        bands.join(", "),
+        wiphy.supports_ap_and_station()
+    );
+
     Ok(())
 }
 
+async fn should_start_portal(client: &Client, opts: &Opts) -> Result<bool> {
+    match opts.start_when {
+        StartWhen::Always => Ok(true),
+        StartWhen::NoConnectivity => {
+            if has_full_connectivity(client).await? {
+                println!("Device is online, not starting portal");
+                return Ok(false);
+            }
+
+            Ok(true)
+        }
+        StartWhen::NoSavedWifi => {
+            if has_saved_wifi_connections(client) {
+                println!("Saved WiFi connections found, not starting portal");
+                return Ok(false);
+            }
+
+            Ok(true)
+        }
+    }
+}
+
+async fn start_portal_after_grace_period() {
+    if let Err(err) = try_start_portal_after_grace_period().await {
+        println!("Failed to create captive portal: {:#}", err);
+    }
+}
+
+/// Starts the portal unless the device came online during the grace period.
+/// Connection attempts through the API are waited for, they either provision
+/// the device or bring the portal up themselves when failing.
+async fn try_start_portal_after_grace_period() -> Result<()> {
+    let client = get_global_client()?;
+    let device = get_global_device()?;
+    let session = get_global_session()?;
+    let opts = get_global_opts()?;
+
+    println!(
+        "Waiting {} seconds for NetworkManager to connect...",
+        opts.start_grace_period
+    );
+
+    glib::timeout_future_seconds(opts.start_grace_period).await;
+
+    while is_global_connecting()? {
+        glib::timeout_future_seconds(1).await;
+    }
+
+    if get_global_portal_state()? != PortalState::Inactive {
+        return Ok(());
+    }
+
+    if has_full_connectivity(&client).await? {
+        println!("Device came online, not starting portal");
+        return Ok(());
+    }
+
+    let portal_connection = create_portal(&client, &device, session.as_ref(), &opts).await?;
+    set_global_portal_connection(portal_connection)
+}
+
+async fn has_full_connectivity(client: &Client) -> Result<bool> {
+    let connectivity = client
+        .check_connectivity_future()
+        .await
+        .context("Failed to execute check connectivity")?;
+
+    Ok(connectivity == ConnectivityState::Full)
+}
+
+fn has_saved_wifi_connections(client: &Client) -> bool {
+    client.connections().into_iter().any(|connection| {
+        let c = connection.upcast::<Connection>();
+        is_wifi_connection(&c) && !is_access_point_mode(&c)
+    })
+}
+
 fn dispatch_command_requests(command_request: CommandRequest) -> glib::Continue {
     let CommandRequest { responder, command } = command_request;
     match command {
         Command::CheckConnectivity => spawn(check_connectivity(), responder),
         Command::ListConnections => spawn(list_connections(), responder),
         Command::ListWiFiNetworks => spawn(list_wifi_networks(), responder),
+        Command::Rescan { ssids } => spawn(rescan(ssids), responder),
+        Command::Connect {
+            ssid,
+            passphrase,
+            identity,
+            hidden,
+        } => spawn(connect(ssid, passphrase, identity, hidden), responder),
         Command::Shutdown => spawn(shutdown(), responder),
         Command::Stop => spawn(stop(), responder),
     };
@@ -295,25 +689,171 @@ async fn list_connections() -> Result<CommandResponce> {
 }
 
 async fn list_wifi_networks() -> Result<CommandResponce> {
-    Ok(CommandResponce::ListWiFiNetworks(NetworkList::new(
-        get_global_stations()?,
-    )))
+    Ok(CommandResponce::ListWiFiNetworks(get_global_networks()?))
+}
+
+/// Scans with nl80211, probing for the given hidden SSIDs in addition to
+/// the broadcasting networks.
+async fn rescan(ssids: Vec<String>) -> Result<CommandResponce> {
+    let device = get_global_device()?;
+    let runtime = get_global_runtime()?;
+    let session = get_global_session()?.context(NL80211_NOT_AVAILABLE)?;
+    let timeout = Duration::from_secs(get_global_opts()?.scan_timeout);
+
+    let interface = device.upcast::<Device>().iface().unwrap().to_string();
+
+    println!("Rescanning for networks on {}...", interface);
+
+    // The nl80211 scan runs on the tokio runtime as its timeout uses the tokio
+    // timer. Unlike the NetworkManager scan it works while the access point
+    // is up.
+    let options = nl80211::scan::ScanOptions::with_ssids(ssids);
+
+    let bsss = runtime
+        .spawn(async move { nl80211::scan::scan(&session, &interface, &options, timeout).await })
+        .await
+        .context("Failed to join scan task")?
+        .context("Failed to scan for networks")?;
+
+    let stations = bsss
+        .iter()
+        .filter(|bss| !bss.hidden && bss.connectable)
+        .map(Station::from)
+        .collect();
+
+    let hidden_channels = bsss
+        .iter()
+        .filter(|bss| bss.hidden)
+        .filter_map(|bss| bss.channel)
+        .collect();
+
+    let networks = NetworkList::new(strongest_stations(stations), hidden_channels);
+
+    set_global_networks(networks.clone())?;
+
+    Ok(CommandResponce::Rescan(networks))
+}
+
+/// Keeps a single entry for each SSID as every access point of a network
+/// shows up in the scan results.
+fn strongest_stations(mut stations: Vec<Station>) -> Vec<Station> {
+    stations.sort_by(|a, b| b.quality.cmp(&a.quality));
+
+    let mut seen = HashSet::new();
+    stations.retain(|station| seen.insert(station.ssid.clone()));
+
+    stations
 }
 
-fn get_global_stations() -> Result<Vec<Station>> {
+fn get_global_networks() -> Result<NetworkList> {
     GLOBAL.with(|global| {
         if let Some(ref state) = *global.borrow() {
-            Ok(state.stations.clone())
+            Ok(state.networks.clone())
         } else {
             Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
         }
     })
 }
 
-fn get_global_portal_connection() -> Result<Option<ActiveConnection>> {
+fn find_global_station_security(ssid: &str) -> Result<Option<Security>> {
+    let networks = get_global_networks()?;
+
+    Ok(networks
+        .stations
+        .iter()
+        .find(|station| station.ssid == ssid)
+        .map(|station| station.security))
+}
+
+fn set_global_networks(networks: NetworkList) -> Result<()> {
+    GLOBAL.with(|global| {
+        if let Some(ref mut state) = *global.borrow_mut() {
+            state.networks = networks;
+            Ok(())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn get_global_runtime() -> Result<Handle> {
+    GLOBAL.with(|global| {
+        if let Some(ref state) = *global.borrow() {
+            Ok(state.runtime.clone())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn get_global_session() -> Result<Option<Nl80211Session>> {
+    GLOBAL.with(|global| {
+        if let Some(ref state) = *global.borrow() {
+            Ok(state.session.clone())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+#[allow(clippy::as_conversions, clippy::cast_possible_truncation)]
+fn timestamp_msec() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+fn take_global_portal_connection() -> Result<Option<ActiveConnection>> {
+    GLOBAL.with(|global| {
+        if let Some(ref mut state) = *global.borrow_mut() {
+            let portal_connection = state.portal_connection.take();
+            if portal_connection.is_some() {
+                state.portal_state.send_replace(PortalState::Inactive);
+            }
+            Ok(portal_connection)
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn set_global_portal_connection(portal_connection: ActiveConnection) -> Result<()> {
+    GLOBAL.with(|global| {
+        if let Some(ref mut state) = *global.borrow_mut() {
+            state.portal_connection = Some(portal_connection);
+            state.portal_state.send_replace(PortalState::Active);
+            Ok(())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn get_global_portal_state() -> Result<PortalState> {
     GLOBAL.with(|global| {
         if let Some(ref state) = *global.borrow() {
-            Ok(state.portal_connection.clone())
+            Ok(*state.portal_state.borrow())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn is_global_connecting() -> Result<bool> {
+    GLOBAL.with(|global| {
+        if let Some(ref state) = *global.borrow() {
+            Ok(state.connecting > 0)
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn set_global_provisioned() -> Result<()> {
+    GLOBAL.with(|global| {
+        if let Some(ref state) = *global.borrow() {
+            state.portal_state.send_replace(PortalState::Provisioned);
+            Ok(())
         } else {
             Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
         }
@@ -330,15 +870,176 @@ fn get_global_client() -> Result<Client> {
     })
 }
 
+fn get_global_device() -> Result<DeviceWifi> {
+    GLOBAL.with(|global| {
+        if let Some(ref state) = *global.borrow() {
+            Ok(state.device.clone())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+fn get_global_opts() -> Result<Opts> {
+    GLOBAL.with(|global| {
+        if let Some(ref state) = *global.borrow() {
+            Ok(state.opts.clone())
+        } else {
+            Err(anyhow!(NETWORK_THREAD_NOT_INITIALIZED))
+        }
+    })
+}
+
+async fn connect(
+    ssid: String,
+    passphrase: Option<String>,
+    identity: Option<String>,
+    hidden: bool,
+) -> Result<CommandResponce> {
+    let client = get_global_client()?;
+    let device = get_global_device()?;
+
+    // Networks that were not found by the scan, e.g. hidden ones, are
+    // connected to based on the credentials provided
+    let security = find_global_station_security(&ssid)?;
+
+    if let Some(security) = security {
+        security.validate(&ssid, passphrase.as_deref(), identity.as_deref())?;
+    }
+
+    let session = get_global_session()?;
+    let opts = get_global_opts()?;
+
+    let _connecting = ConnectingGuard::new()?;
+
+    // With a virtual access point interface the portal stays up while the
+    // credentials are tested
+    let concurrent = opts.ap_interface.is_some();
+
+    if !concurrent {
+        if let Some(active_connection) = take_global_portal_connection()? {
+            let result = stop_portal(
+                &client,
+                &device,
+                &active_connection,
+                session.as_ref(),
+                &opts,
+            )
+            .await;
+
+            // Otherwise the device would be left without both the portal and
+            // a connection
+            if let Err(err) = result {
+                if let Err(err) = recreate_portal(&client, &device, session.as_ref(), &opts).await {
+                    println!("{:#}", err);
+                }
+
+                return Err(err.context("Failed to stop captive portal"));
+            }
+        }
+    }
+
+    let interface = device.clone().upcast::<Device>().iface().unwrap();
+
+    let connection = create_client_connection(
+        interface.as_str(),
+        &ssid,
+        &passphrase.as_deref(),
+        &identity.as_deref(),
+        security,
+        hidden,
+    );
+
+    let result = activate_client_connection(&client, &device, &connection).await;
+
+    if let Ok(ActiveConnectionState::Activated) = result {
+        if let Some(active_connection) = take_global_portal_connection()? {
+            stop_portal(
+                &client,
+                &device,
+                &active_connection,
+                session.as_ref(),
+                &opts,
+            )
+            .await?;
+        }
+
+        set_global_provisioned()?;
+    } else if !concurrent {
+        if let Err(ref err) = result {
+            println!("Failed to connect to '{}': {:#}", ssid, err);
+        }
+
+        // Bring the captive portal back so that the user can try again
+        recreate_portal(&client, &device, session.as_ref(), &opts).await?;
+    }
+
+    match result? {
+        ActiveConnectionState::Activated => Ok(CommandResponce::Connect(Connect::new(
+            ssid,
+            format!("{:?}", ActiveConnectionState::Activated),
+        ))),
+        state => Err(ConnectionFailed::new(ssid, format!("{:?}", state)).into()),
+    }
+}
+
+async fn recreate_portal(
+    client: &Client,
+    device: &DeviceWifi,
+    session: Option<&Nl80211Session>,
+    opts: &Opts,
+) -> Result<()> {
+    let portal_connection = create_portal(client, device, session, opts)
+        .await
+        .context("Failed to recreate captive portal")?;
+
+    set_global_portal_connection(portal_connection)
+}
+
+/// Removes the connection profile again when the connection does not come up.
+async fn activate_client_connection(
+    client: &Client,
+    device: &DeviceWifi,
+    connection: &SimpleConnection,
+) -> Result<ActiveConnectionState> {
+    let active_connection = client
+        .add_and_activate_connection_future(Some(connection), device, None)
+        .await
+        .context("Failed to add and activate connection")?;
+
+    let state = finalize_active_connection_state(&active_connection).await?;
+
+    if state != ActiveConnectionState::Activated {
+        if let Some(remote_connection) = active_connection.connection() {
+            remote_connection
+                .delete_future()
+                .await
+                .context("Failed to delete connection profile after failing to activate")?;
+        }
+    }
+
+    Ok(state)
+}
+
 async fn shutdown() -> Result<CommandResponce> {
     Ok(CommandResponce::Shutdown(Shutdown::new("ok")))
 }
 
 async fn stop() -> Result<CommandResponce> {
     let client = get_global_client()?;
-
-    if let Some(active_connection) = get_global_portal_connection()? {
-        stop_portal(&client, &active_connection).await?;
+    let device = get_global_device()?;
+    let session = get_global_session()?;
+    let opts = get_global_opts()?;
+
+    if let Some(active_connection) = take_global_portal_connection()? {
+        stop_portal(
+            &client,
+            &device,
+            &active_connection,
+            session.as_ref(),
+            &opts,
+        )
+        .await?;
     }
 
     Ok(CommandResponce::Stop(Stop::new("ok")))
@@ -380,11 +1081,28 @@ fn get_nearby_access_points(device: &DeviceWifi) -> Vec<AccessPoint> {
     access_points.retain(|ap| inserted.insert(ap_ssid(ap)));
 
     // Purge access points without SSID (hidden)
-    access_points.retain(|ap| !ap_ssid(ap).is_empty());
+    access_points.retain(|ap| !ap.ssid().map_or(true, |ssid| is_hidden_ssid(&ssid)));
 
     access_points
 }
 
+/// Channels of access points that do not broadcast their SSID. These are
+/// left out of the nearby access points.
+fn get_hidden_channels(device: &DeviceWifi) -> Vec<u32> {
+    device
+        .access_points()
+        .iter()
+        .filter(|ap| ap.ssid().map_or(true, |ssid| is_hidden_ssid(&ssid)))
+        .map(|ap| utils_wifi_freq_to_channel(ap.frequency()))
+        .filter(|channel| *channel != 0)
+        .collect()
+}
+
+/// Hidden networks either send an empty SSID or one made of zero bytes.
+pub fn is_hidden_ssid(ssid: &[u8]) -> bool {
+    ssid.iter().all(|byte| *byte == 0)
+}
+
 fn ssid_to_string(ssid: Option<glib::Bytes>) -> Option<String> {
     // An access point SSID could be random bytes and not a UTF-8 encoded string
     std::str::from_utf8(&ssid?).ok().map(str::to_owned)
@@ -492,7 +1210,40 @@ fn find_any_wifi_device(client: &Client) -> Result<DeviceWifi> {
 async fn create_portal(
     client: &Client,
     device: &DeviceWifi,
+    session: Option<&Nl80211Session>,
     opts: &Opts,
+) -> Result<ActiveConnection> {
+    let channel = select_ap_channel(session, device, opts).await;
+
+    let ap_interface = match opts.ap_interface {
+        Some(ref ap_interface) => ap_interface,
+        None => return activate_portal(client, device, opts, channel).await,
+    };
+
+    let session = session.context(NL80211_NOT_AVAILABLE)?;
+
+    let result = async {
+        let ap_device = create_virtual_ap_device(client, device, session, ap_interface).await?;
+
+        activate_portal(client, &ap_device, opts, channel).await
+    }
+    .await;
+
+    // Otherwise the next attempt would find the interface still around
+    if result.is_err() {
+        if let Err(err) = delete_virtual_ap_interface(session, device, ap_interface).await {
+            println!("{:#}", err);
+        }
+    }
+
+    result
+}
+
+async fn activate_portal(
+    client: &Client,
+    device: &DeviceWifi,
+    opts: &Opts,
+    channel: Option<(u32, Band)>,
 ) -> Result<ActiveConnection> {
     let interface = device.clone().upcast::<Device>().iface().unwrap();
 
@@ -501,6 +1252,8 @@ async fn create_portal(
         &opts.ssid,
         &opts.gateway,
         &opts.password.as_deref(),
+        opts.dhcp,
+        channel,
     )?;
 
     let active_connection = client
@@ -523,7 +1276,13 @@ async fn create_portal(
     }
 }
 
-async fn stop_portal(client: &Client, active_connection: &ActiveConnection) -> Result<()> {
+async fn stop_portal(
+    client: &Client,
+    device: &DeviceWifi,
+    active_connection: &ActiveConnection,
+    session: Option<&Nl80211Session>,
+    opts: &Opts,
+) -> Result<()> {
     client
         .deactivate_connection_future(active_connection)
         .await?;
@@ -537,9 +1296,154 @@ async fn stop_portal(client: &Client, active_connection: &ActiveConnection) -> R
             .context("Failed to delete captive portal connection profile")?;
     }
 
+    if let Some(ref ap_interface) = opts.ap_interface {
+        let session = session.context(NL80211_NOT_AVAILABLE)?;
+        delete_virtual_ap_interface(session, device, ap_interface).await?;
+    }
+
     Ok(())
 }
 
+/// Resolves `--ap-channel` to a channel and its band. When no channel can be
+/// picked automatically the choice is left to NetworkManager instead of
+/// failing the portal.
+async fn select_ap_channel(
+    session: Option<&Nl80211Session>,
+    device: &DeviceWifi,
+    opts: &Opts,
+) -> Option<(u32, Band)> {
+    match opts.ap_channel? {
+        ApChannel::Fixed(channel) => Some((channel, channel_band(channel))),
+        ApChannel::Auto => {
+            let interface = device.clone().upcast::<Device>().iface().unwrap();
+
+            let result = match session {
+                Some(session) => select_auto_ap_channel(session, interface.as_str(), opts).await,
+                None => Err(anyhow!(NL80211_NOT_AVAILABLE)),
+            };
+
+            match result {
+                Ok(channel) => Some(channel),
+                Err(err) => {
+                    println!("Failed to select access point channel: {:#}", err);
+                    None
+                }
+            }
+        }
+    }
+}
+
+async fn select_auto_ap_channel(
+    session: &Nl80211Session,
+    interface: &str,
+    opts: &Opts,
+) -> Result<(u32, Band)> {
+    // A radio shared with a connected station can only use the channel of
+    // its link
+    if opts.ap_interface.is_some() {
+        let link_status = nl80211::link::get_link(session, interface)
+            .await
+            .context("Failed to get link status")?;
+
+        if let Some(link) = link_status.link {
+            if let Some(channel) = link.channel {
+                let band = match link.band {
+                    Some(band @ (Band::Band2GHz | Band::Band5GHz)) => band,
+                    _ => bail!(
+                        "Access point cannot share the station link on {} MHz",
+                        link.frequency
+                    ),
+                };
+
+                println!("Access point channel: {} (station link)", channel);
+                return Ok((channel, band));
+            }
+        }
+    }
+
+    let scores = nl80211::survey::score_channels(session, interface, Band::Band2GHz)
+        .await
+        .context("Failed to score channels")?;
+
+    let best = scores
+        .first()
+        .context("No channel available for an access point")?;
+
+    println!(
+        "Access point channel: {} (score {}, {} access points nearby)",
+        best.channel, best.score, best.bss_count
+    );
+
+    Ok((best.channel, Band::Band2GHz))
+}
+
+/// Band of a `--ap-channel` number. NetworkManager starts access points on
+/// the 2.4GHz and 5GHz bands only, where channel numbers of 14 and below are
+/// on the 2.4GHz band. The same numbers are reused on the 6GHz band.
+fn channel_band(channel: u32) -> Band {
+    if channel <= 14 {
+        Band::Band2GHz
+    } else {
+        Band::Band5GHz
+    }
+}
+
+/// Creates the virtual access point interface on the radio of `device` and
+/// waits for NetworkManager to take it over.
+async fn create_virtual_ap_device(
+    client: &Client,
+    device: &DeviceWifi,
+    session: &Nl80211Session,
+    ap_interface: &str,
+) -> Result<DeviceWifi> {
+    let parent = device.clone().upcast::<Device>().iface().unwrap();
+
+    nl80211::virtual_interface::create_ap_interface(session, parent.as_str(), ap_interface)
+        .await
+        .context(format!(
+            "Failed to create virtual interface '{}'",
+            ap_interface
+        ))?;
+
+    println!("Virtual access point interface: {}", ap_interface);
+
+    for _ in 0..VIRTUAL_INTERFACE_TIMEOUT_SECONDS {
+        if let Some(ap_device) = client.device_by_iface(ap_interface) {
+            match ap_device.state() {
+                DeviceState::Unmanaged => ap_device.set_managed(true),
+                DeviceState::Unknown | DeviceState::Unavailable => {}
+                _ => {
+                    return ap_device
+                        .downcast()
+                        .map_err(|_| anyhow!("Not a WiFi interface '{}'", ap_interface));
+                }
+            }
+        }
+
+        glib::timeout_future_seconds(1).await;
+    }
+
+    bail!(
+        "Interface '{}' was not taken over by NetworkManager",
+        ap_interface
+    )
+}
+
+async fn delete_virtual_ap_interface(
+    session: &Nl80211Session,
+    device: &DeviceWifi,
+    ap_interface: &str,
+) -> Result<()> {
+    let parent = device.clone().upcast::<Device>().iface().unwrap();
+
+    nl80211::virtual_interface::delete_ap_interface(session, parent.as_str(), ap_interface)
+        .await
+        .context(format!(
+            "Failed to delete virtual interface '{}'",
+            ap_interface
+        ))
+}
+
 async fn finalize_active_connection_state(
     active_connection: &ActiveConnection,
 ) -> Result<ActiveConnectionState> {
@@ -580,6 +1484,8 @@ fn create_ap_connection(
     ssid: &str,
     address: &str,
     passphrase: &Option<&str>,
+    dhcp: DhcpMode,
+    channel: Option<(u32, Band)>,
 ) -> Result<SimpleConnection> {
     let connection = SimpleConnection::new();
 
@@ -592,7 +1498,19 @@ fn create_ap_connection(
 
     let s_wireless = SettingWireless::new();
     s_wireless.set_ssid(Some(&(ssid.as_bytes().into())));
-    s_wireless.set_band(Some("bg"));
+    match channel {
+        Some((channel, band)) => {
+            // NetworkManager names the 2.4GHz band `bg` and the 5GHz one `a`
+            let band = match band {
+                Band::Band2GHz => "bg",
+                Band::Band5GHz => "a",
+                _ => bail!("Access point cannot be started on the {} band", band),
+            };
+            s_wireless.set_band(Some(band));
+            s_wireless.set_channel(channel);
+        }
+        None => s_wireless.set_band(Some("bg")),
+    }
     s_wireless.set_hidden(false);
     s_wireless.set_mode(Some(&SETTING_WIRELESS_MODE_AP));
     connection.add_setting(&s_wireless);
@@ -608,12 +1526,93 @@ fn create_ap_connection(
     let address =
         IPAddress::new(libc::AF_INET, address, 24).context("Failed to parse gateway address")?;
     s_ip4.add_address(&address);
-    s_ip4.set_method(Some(&SETTING_IP4_CONFIG_METHOD_MANUAL));
+    if dhcp == DhcpMode::Shared {
+        // NetworkManager runs its own DHCP and DNS server in shared mode
+        s_ip4.set_method(Some(&SETTING_IP4_CONFIG_METHOD_SHARED));
+    } else {
+        s_ip4.set_method(Some(&SETTING_IP4_CONFIG_METHOD_MANUAL));
+    }
     connection.add_setting(&s_ip4);
 
     Ok(connection)
 }
 
+fn create_client_connection(
+    interface: &str,
+    ssid: &str,
+    passphrase: &Option<&str>,
+    identity: &Option<&str>,
+    security: Option<Security>,
+    hidden: bool,
+) -> SimpleConnection {
+    let connection = SimpleConnection::new();
+
+    let s_connection = SettingConnection::new();
+    s_connection.set_type(Some(&SETTING_WIRELESS_SETTING_NAME));
+    s_connection.set_id(Some(ssid));
+    s_connection.set_autoconnect(true);
+    s_connection.set_interface_name(Some(interface));
+    connection.add_setting(&s_connection);
+
+    let s_wireless = SettingWireless::new();
+    s_wireless.set_ssid(Some(&(ssid.as_bytes().into())));
+    s_wireless.set_mode(Some(&SETTING_WIRELESS_MODE_INFRA));
+    // Hidden networks do not answer broadcast probes, so they have to be
+    // probed for by name
+    s_wireless.set_hidden(hidden);
+    connection.add_setting(&s_wireless);
+
+    if security == Some(Security::Owe) {
+        let s_wireless_security = SettingWirelessSecurity::new();
+        s_wireless_security.set_key_mgmt(Some("owe"));
+        connection.add_setting(&s_wireless_security);
+    }
+
+    if let Some(password) = *passphrase {
+        let s_wireless_security = SettingWirelessSecurity::new();
+
+        if security == Some(Security::Wep) {
+            s_wireless_security.set_key_mgmt(Some("none"));
+            s_wireless_security.set_wep_key_type(WepKeyType::Key);
+            s_wireless_security.set_wep_key0(Some(password));
+        } else if security == Some(Security::Wpa3) {
+            s_wireless_security.set_key_mgmt(Some("sae"));
+            s_wireless_security.set_psk(Some(password));
+        } else if let (Some(identity), None | Some(Security::Enterprise)) = (*identity, security) {
+            s_wireless_security.set_key_mgmt(Some("wpa-eap"));
+
+            let s_8021x = Setting8021x::new();
+            s_8021x.add_eap_method("peap");
+            s_8021x.set_identity(Some(identity));
+            s_8021x.set_password(Some(password));
+            s_8021x.set_phase2_auth(Some("mschapv2"));
+            connection.add_setting(&s_8021x);
+        } else {
+            s_wireless_security.set_key_mgmt(Some("wpa-psk"));
+            s_wireless_security.set_psk(Some(password));
+        }
+
+        connection.add_setting(&s_wireless_security);
+    }
+
+    connection
+}
+
+/// Waits until the portal is either up or down as requested by `active`.
+/// Returns `false` if the network thread has gone away in the meantime.
+pub async fn wait_for_portal_active(
+    portal_state: &mut watch::Receiver<PortalState>,
+    active: bool,
+) -> bool {
+    while (*portal_state.borrow_and_update() == PortalState::Active) != active {
+        if portal_state.changed().await.is_err() {
+            return false;
+        }
+    }
+
+    true
+}
+
 pub fn spawn_local<F: Future<Output = ()> + 'static>(f: F) {
     glib::MainContext::ref_thread_default().spawn_local(f);
 }
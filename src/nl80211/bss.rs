@@ -0,0 +1,273 @@
+use std::convert::{TryFrom, TryInto};
+
+use anyhow::Context;
+
+use macaddr::MacAddr6;
+
+use neli::attr::Attribute;
+use neli::genl::Genlmsghdr;
+
+use serde::{Serialize, Serializer};
+
+use crate::network::{is_hidden_ssid, Security, Station};
+use crate::nl80211::consts;
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Bss, Nl80211Cmd};
+use crate::nl80211::ie::{self, Element, Rsn, Suite};
+
+const CAPABILITY_PRIVACY: u16 = 0x0010;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Band {
+    #[serde(rename = "2.4GHz")]
+    Band2GHz,
+    #[serde(rename = "5GHz")]
+    Band5GHz,
+    #[serde(rename = "6GHz")]
+    Band6GHz,
+    #[serde(rename = "60GHz")]
+    Band60GHz,
+    #[serde(rename = "900MHz")]
+    BandS1GHz,
+}
+
+impl std::fmt::Display for Band {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match *self {
+            Self::Band2GHz => "2.4GHz",
+            Self::Band5GHz => "5GHz",
+            Self::Band6GHz => "6GHz",
+            Self::Band60GHz => "60GHz",
+            Self::BandS1GHz => "900MHz",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for Band {
+    type Err = anyhow::Error;
+
+    /// Accepts the names used in the API, with or without the unit.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "2.4ghz" | "2.4" | "2" => Ok(Self::Band2GHz),
+            "5ghz" | "5" => Ok(Self::Band5GHz),
+            "6ghz" | "6" => Ok(Self::Band6GHz),
+            "60ghz" | "60" => Ok(Self::Band60GHz),
+            "900mhz" | "900" => Ok(Self::BandS1GHz),
+            _ => Err(anyhow::anyhow!("Unknown band '{}'", s)),
+        }
+    }
+}
+
+impl Band {
+    pub fn from_nl80211(band: u16) -> Option<Self> {
+        match u32::from(band) {
+            consts::NL80211_BAND_2GHZ => Some(Self::Band2GHz),
+            consts::NL80211_BAND_5GHZ => Some(Self::Band5GHz),
+            consts::NL80211_BAND_6GHZ => Some(Self::Band6GHz),
+            consts::NL80211_BAND_60GHZ => Some(Self::Band60GHz),
+            consts::NL80211_BAND_S1GHZ => Some(Self::BandS1GHz),
+            _ => None,
+        }
+    }
+}
+
+/// A single access point as reported by an nl80211 scan.
+#[derive(Serialize, Debug, Clone)]
+pub struct Bss {
+    #[serde(serialize_with = "serialize_mac")]
+    pub bssid: MacAddr6,
+    /// Empty for hidden networks
+    pub ssid: String,
+    pub hidden: bool,
+    /// The SSID is valid UTF-8. Other SSIDs are shown with replacement
+    /// characters and cannot be connected to by name.
+    pub connectable: bool,
+    /// Center frequency in MHz
+    pub frequency: u32,
+    pub channel: Option<u32>,
+    pub band: Option<Band>,
+    pub signal_dbm: i32,
+    pub seen_ms_ago: u32,
+    /// Operating channel width of the access point
+    pub chan_width_mhz: u32,
+    pub security: Security,
+    /// The interface is associated with this access point
+    pub associated: bool,
+}
+
+impl Bss {
+    pub fn quality(&self) -> u8 {
+        dbm_level_to_quality(self.signal_dbm)
+    }
+}
+
+impl TryFrom<&Genlmsghdr<Nl80211Cmd, Nl80211Attr>> for Bss {
+    type Error = anyhow::Error;
+
+    fn try_from(payload: &Genlmsghdr<Nl80211Cmd, Nl80211Attr>) -> Result<Self, Self::Error> {
+        let mut attrs = payload.get_attr_handle();
+        let bss_attrs = attrs.get_nested_attributes::<Nl80211Bss>(Nl80211Attr::Bss)?;
+
+        let bssid_bytes: [u8; 6] = bss_attrs
+            .get_attr_payload_as_with_len::<&[u8]>(Nl80211Bss::Bssid)?
+            .try_into()?;
+        let bssid = bssid_bytes.into();
+        let frequency = bss_attrs.get_attr_payload_as(Nl80211Bss::Frequency)?;
+        let signal_mbm = bss_attrs.get_attr_payload_as::<i32>(Nl80211Bss::SignalMbm)?;
+        let seen_ms_ago = bss_attrs.get_attr_payload_as(Nl80211Bss::SeenMsAgo)?;
+
+        let ie_attrs = bss_attrs
+            .get_attribute(Nl80211Bss::InformationElements)
+            .context("Missing information elements")?;
+        let ies = ie_attrs.payload().as_ref();
+        let ssid_bytes = ie::find_ssid(ies).unwrap_or_default();
+        let hidden = is_hidden_ssid(ssid_bytes);
+        let connectable = std::str::from_utf8(ssid_bytes).is_ok();
+        let ssid = if hidden {
+            String::new()
+        } else {
+            String::from_utf8_lossy(ssid_bytes).into_owned()
+        };
+        let chan_width_mhz = ie::operating_width_mhz(ies);
+
+        let capability = bss_attrs
+            .get_attr_payload_as::<u16>(Nl80211Bss::Capability)
+            .unwrap_or_default();
+        let security = security_from_ies(ies, capability & CAPABILITY_PRIVACY != 0);
+
+        let associated = bss_attrs
+            .get_attr_payload_as::<u32>(Nl80211Bss::Status)
+            .map_or(false, |status| {
+                status == consts::NL80211_BSS_STATUS_ASSOCIATED
+            });
+
+        Ok(Self {
+            bssid,
+            ssid,
+            hidden,
+            connectable,
+            frequency,
+            channel: frequency_to_channel(frequency),
+            band: frequency_to_band(frequency),
+            signal_dbm: signal_mbm / 100,
+            seen_ms_ago,
+            chan_width_mhz,
+            security,
+            associated,
+        })
+    }
+}
+
+impl From<&Bss> for Station {
+    fn from(bss: &Bss) -> Self {
+        Self {
+            ssid: bss.ssid.clone(),
+            quality: bss.quality(),
+            security: bss.security,
+        }
+    }
+}
+
+pub fn frequency_to_band(frequency: u32) -> Option<Band> {
+    match frequency {
+        2401..=2495 => Some(Band::Band2GHz),
+        5150..=5895 => Some(Band::Band5GHz),
+        5925..=7125 => Some(Band::Band6GHz),
+        58320..=70200 => Some(Band::Band60GHz),
+        _ => None,
+    }
+}
+
+pub fn frequency_to_channel(frequency: u32) -> Option<u32> {
+    match frequency {
+        2484 => Some(14),
+        2412..=2472 => Some((frequency - 2407) / 5),
+        5935 => Some(2),
+        5955..=7115 => Some((frequency - 5950) / 5),
+        5150..=5895 => Some((frequency - 5000) / 5),
+        _ => None,
+    }
+}
+
+/// Classifies a network by its RSN and WPA elements, falling back to the
+/// privacy capability bit for WEP.
+pub fn security_from_ies(ies: &[u8], privacy: bool) -> Security {
+    let mut rsn = None;
+    let mut wpa = None;
+
+    for element in ie::elements(ies) {
+        match element {
+            Element::Rsn(element) => rsn = Some(element),
+            Element::Wpa(element) => wpa = Some(element),
+            _ => {}
+        }
+    }
+
+    if let Some(rsn) = rsn {
+        rsn_security(&rsn)
+    } else if let Some(wpa) = wpa {
+        let wpa_8021x = Suite {
+            oui: ie::OUI_MICROSOFT,
+            suite_type: 1,
+        };
+
+        if wpa.akm_suites.contains(wpa_8021x) {
+            Security::Enterprise
+        } else {
+            Security::Wpa
+        }
+    } else if privacy {
+        Security::Wep
+    } else {
+        Security::Open
+    }
+}
+
+fn rsn_security(rsn: &Rsn<'_>) -> Security {
+    let akms = &rsn.akm_suites;
+
+    let is_enterprise = [
+        Suite::AKM_8021X,
+        Suite::AKM_FT_8021X,
+        Suite::AKM_8021X_SHA256,
+        Suite::AKM_8021X_SUITE_B,
+        Suite::AKM_8021X_SUITE_B_192,
+    ]
+    .into_iter()
+    .any(|akm| akms.contains(akm));
+
+    let has_psk = [Suite::AKM_PSK, Suite::AKM_FT_PSK, Suite::AKM_PSK_SHA256]
+        .into_iter()
+        .any(|akm| akms.contains(akm));
+
+    let has_sae = akms.contains(Suite::AKM_SAE) || akms.contains(Suite::AKM_FT_SAE);
+
+    if is_enterprise {
+        Security::Enterprise
+    } else if has_sae && !has_psk {
+        Security::Wpa3
+    } else if akms.contains(Suite::AKM_OWE) {
+        Security::Owe
+    } else {
+        Security::Wpa2
+    }
+}
+
+pub(super) fn serialize_mac<S: Serializer>(
+    mac: &MacAddr6,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(mac)
+}
+
+#[allow(clippy::as_conversions)]
+fn dbm_level_to_quality(signal_dbm: i32) -> u8 {
+    let mut val = f64::from(signal_dbm);
+    val = val.clamp(-100., -40.);
+    val = (val + 40.).abs();
+    val = (100. - (100. * val) / 60.).round();
+    val = val.clamp(0., 100.);
+    val as u8
+}
@@ -494,3 +494,115 @@ pub enum Nl80211Bss {
 }
 
 impl neli::consts::genl::NlAttrType for Nl80211Bss {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211BandAttr {
+    Freqs = NL80211_BAND_ATTR_FREQS as u16,
+    Rates = NL80211_BAND_ATTR_RATES as u16,
+    HtMcsSet = NL80211_BAND_ATTR_HT_MCS_SET as u16,
+    HtCapa = NL80211_BAND_ATTR_HT_CAPA as u16,
+    HtAmpduFactor = NL80211_BAND_ATTR_HT_AMPDU_FACTOR as u16,
+    HtAmpduDensity = NL80211_BAND_ATTR_HT_AMPDU_DENSITY as u16,
+    VhtMcsSet = NL80211_BAND_ATTR_VHT_MCS_SET as u16,
+    VhtCapa = NL80211_BAND_ATTR_VHT_CAPA as u16,
+    IftypeData = NL80211_BAND_ATTR_IFTYPE_DATA as u16,
+    EdmgChannels = NL80211_BAND_ATTR_EDMG_CHANNELS as u16,
+    EdmgBwConfig = NL80211_BAND_ATTR_EDMG_BW_CONFIG as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211BandAttr {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211FrequencyAttr {
+    Freq = NL80211_FREQUENCY_ATTR_FREQ as u16,
+    Disabled = NL80211_FREQUENCY_ATTR_DISABLED as u16,
+    NoIr = NL80211_FREQUENCY_ATTR_NO_IR as u16,
+    Radar = NL80211_FREQUENCY_ATTR_RADAR as u16,
+    MaxTxPower = NL80211_FREQUENCY_ATTR_MAX_TX_POWER as u16,
+    DfsState = NL80211_FREQUENCY_ATTR_DFS_STATE as u16,
+    DfsTime = NL80211_FREQUENCY_ATTR_DFS_TIME as u16,
+    NoHt40Minus = NL80211_FREQUENCY_ATTR_NO_HT40_MINUS as u16,
+    NoHt40Plus = NL80211_FREQUENCY_ATTR_NO_HT40_PLUS as u16,
+    No80Mhz = NL80211_FREQUENCY_ATTR_NO_80MHZ as u16,
+    No160Mhz = NL80211_FREQUENCY_ATTR_NO_160MHZ as u16,
+    DfsCacTime = NL80211_FREQUENCY_ATTR_DFS_CAC_TIME as u16,
+    IndoorOnly = NL80211_FREQUENCY_ATTR_INDOOR_ONLY as u16,
+    IrConcurrent = NL80211_FREQUENCY_ATTR_IR_CONCURRENT as u16,
+    No20Mhz = NL80211_FREQUENCY_ATTR_NO_20MHZ as u16,
+    No10Mhz = NL80211_FREQUENCY_ATTR_NO_10MHZ as u16,
+    Wmm = NL80211_FREQUENCY_ATTR_WMM as u16,
+    NoHe = NL80211_FREQUENCY_ATTR_NO_HE as u16,
+    Offset = NL80211_FREQUENCY_ATTR_OFFSET as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211FrequencyAttr {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211IfaceCombAttr {
+    Limits = NL80211_IFACE_COMB_LIMITS as u16,
+    Maxnum = NL80211_IFACE_COMB_MAXNUM as u16,
+    StaApBiMatch = NL80211_IFACE_COMB_STA_AP_BI_MATCH as u16,
+    NumChannels = NL80211_IFACE_COMB_NUM_CHANNELS as u16,
+    RadarDetectWidths = NL80211_IFACE_COMB_RADAR_DETECT_WIDTHS as u16,
+    RadarDetectRegions = NL80211_IFACE_COMB_RADAR_DETECT_REGIONS as u16,
+    BiMinGcd = NL80211_IFACE_COMB_BI_MIN_GCD as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211IfaceCombAttr {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211IfaceLimitAttr {
+    Max = NL80211_IFACE_LIMIT_MAX as u16,
+    Types = NL80211_IFACE_LIMIT_TYPES as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211IfaceLimitAttr {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211StaInfo {
+    InactiveTime = NL80211_STA_INFO_INACTIVE_TIME as u16,
+    RxBytes = NL80211_STA_INFO_RX_BYTES as u16,
+    TxBytes = NL80211_STA_INFO_TX_BYTES as u16,
+    Signal = NL80211_STA_INFO_SIGNAL as u16,
+    TxBitrate = NL80211_STA_INFO_TX_BITRATE as u16,
+    RxPackets = NL80211_STA_INFO_RX_PACKETS as u16,
+    TxPackets = NL80211_STA_INFO_TX_PACKETS as u16,
+    TxRetries = NL80211_STA_INFO_TX_RETRIES as u16,
+    TxFailed = NL80211_STA_INFO_TX_FAILED as u16,
+    SignalAvg = NL80211_STA_INFO_SIGNAL_AVG as u16,
+    RxBitrate = NL80211_STA_INFO_RX_BITRATE as u16,
+    ConnectedTime = NL80211_STA_INFO_CONNECTED_TIME as u16,
+    StaFlags = NL80211_STA_INFO_STA_FLAGS as u16,
+    RxBytes64 = NL80211_STA_INFO_RX_BYTES64 as u16,
+    TxBytes64 = NL80211_STA_INFO_TX_BYTES64 as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211StaInfo {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211RateInfo {
+    Bitrate = NL80211_RATE_INFO_BITRATE as u16,
+    Mcs = NL80211_RATE_INFO_MCS as u16,
+    Bitrate32 = NL80211_RATE_INFO_BITRATE32 as u16,
+    VhtMcs = NL80211_RATE_INFO_VHT_MCS as u16,
+    VhtNss = NL80211_RATE_INFO_VHT_NSS as u16,
+    HeMcs = NL80211_RATE_INFO_HE_MCS as u16,
+    HeNss = NL80211_RATE_INFO_HE_NSS as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211RateInfo {}
+
+#[neli_enum(serialized_type = "u16")]
+pub enum Nl80211SurveyInfo {
+    Frequency = NL80211_SURVEY_INFO_FREQUENCY as u16,
+    Noise = NL80211_SURVEY_INFO_NOISE as u16,
+    InUse = NL80211_SURVEY_INFO_IN_USE as u16,
+    Time = NL80211_SURVEY_INFO_TIME as u16,
+    TimeBusy = NL80211_SURVEY_INFO_TIME_BUSY as u16,
+    TimeExtBusy = NL80211_SURVEY_INFO_TIME_EXT_BUSY as u16,
+    TimeRx = NL80211_SURVEY_INFO_TIME_RX as u16,
+    TimeTx = NL80211_SURVEY_INFO_TIME_TX as u16,
+    TimeScan = NL80211_SURVEY_INFO_TIME_SCAN as u16,
+}
+
+impl neli::consts::genl::NlAttrType for Nl80211SurveyInfo {}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::io;
+
+/// Failures callers commonly handle differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The radio is busy with another scan or a connection attempt
+    Busy,
+    /// The driver does not implement the request
+    NotSupported,
+    /// The interface is down
+    NetworkDown,
+    /// Missing `CAP_NET_ADMIN`
+    PermissionDenied,
+    /// The interface or station does not exist
+    NoDevice,
+    /// The request was rejected as malformed
+    InvalidArgument,
+    Other,
+}
+
+/// An error the kernel reported in reply to an nl80211 request, with the
+/// explanation from the extended acknowledgement when there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nl80211Error {
+    errno: i32,
+    message: Option<String>,
+}
+
+impl Nl80211Error {
+    pub fn new(errno: i32, message: Option<String>) -> Self {
+        Self { errno, message }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self.errno {
+            libc::EBUSY => ErrorKind::Busy,
+            libc::EOPNOTSUPP => ErrorKind::NotSupported,
+            libc::ENETDOWN => ErrorKind::NetworkDown,
+            libc::EPERM | libc::EACCES => ErrorKind::PermissionDenied,
+            libc::ENODEV | libc::ENOENT => ErrorKind::NoDevice,
+            libc::EINVAL | libc::ERANGE => ErrorKind::InvalidArgument,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+}
+
+impl fmt::Display for Nl80211Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", io::Error::from_raw_os_error(self.errno))?;
+
+        if let Some(ref message) = self.message {
+            write!(f, ": {}", message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Nl80211Error {}
@@ -0,0 +1,725 @@
+//! Decoding of the 802.11 information elements carried in beacons and probe
+//! responses. Elements borrow from the buffer they were parsed from.
+
+use std::convert::TryInto;
+
+const EID_SSID: u8 = 0;
+const EID_SUPPORTED_RATES: u8 = 1;
+const EID_DS_PARAMETER_SET: u8 = 3;
+const EID_COUNTRY: u8 = 7;
+const EID_BSS_LOAD: u8 = 11;
+const EID_HT_CAPABILITIES: u8 = 45;
+const EID_RSN: u8 = 48;
+const EID_EXTENDED_SUPPORTED_RATES: u8 = 50;
+const EID_HT_OPERATION: u8 = 61;
+const EID_EXTENDED_CAPABILITIES: u8 = 127;
+const EID_VHT_CAPABILITIES: u8 = 191;
+const EID_VHT_OPERATION: u8 = 192;
+const EID_VENDOR_SPECIFIC: u8 = 221;
+const EID_EXTENSION: u8 = 255;
+
+const EID_EXT_HE_CAPABILITIES: u8 = 35;
+const EID_EXT_HE_OPERATION: u8 = 36;
+
+pub const OUI_MICROSOFT: [u8; 3] = [0x00, 0x50, 0xf2];
+pub const OUI_IEEE_80211: [u8; 3] = [0x00, 0x0f, 0xac];
+
+const HE_OPERATION_VHT_INFO_PRESENT: u32 = 1 << 14;
+const HE_OPERATION_CO_HOSTED_BSS: u32 = 1 << 15;
+const HE_OPERATION_6GHZ_INFO_PRESENT: u32 = 1 << 17;
+
+const MICROSOFT_TYPE_WPA: u8 = 1;
+const MICROSOFT_TYPE_WPS: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Element<'a> {
+    Ssid(&'a [u8]),
+    SupportedRates(Rates<'a>),
+    ExtendedSupportedRates(Rates<'a>),
+    DsParameterSet {
+        channel: u8,
+    },
+    Country {
+        code: [u8; 2],
+        environment: u8,
+        triplets: &'a [u8],
+    },
+    BssLoad {
+        station_count: u16,
+        channel_utilization: u8,
+        available_admission_capacity: u16,
+    },
+    Rsn(Rsn<'a>),
+    HtCapabilities {
+        capabilities_info: u16,
+        data: &'a [u8],
+    },
+    HtOperation {
+        primary_channel: u8,
+        secondary_channel_offset: u8,
+        any_channel_width: bool,
+        data: &'a [u8],
+    },
+    VhtCapabilities {
+        capabilities_info: u32,
+        data: &'a [u8],
+    },
+    VhtOperation {
+        channel_width: u8,
+        center_frequency_segment0: u8,
+        center_frequency_segment1: u8,
+        data: &'a [u8],
+    },
+    HeCapabilities(&'a [u8]),
+    HeOperation(&'a [u8]),
+    ExtendedCapabilities(ExtendedCapabilities<'a>),
+    Wpa(Rsn<'a>),
+    Wps(&'a [u8]),
+    Microsoft {
+        oui_type: u8,
+        data: &'a [u8],
+    },
+    Vendor {
+        oui: [u8; 3],
+        data: &'a [u8],
+    },
+    /// An element this parser does not decode
+    Unknown {
+        id: u8,
+        data: &'a [u8],
+    },
+    /// A known element whose body is too short or otherwise invalid
+    Malformed {
+        id: u8,
+        data: &'a [u8],
+    },
+}
+
+/// Iterates over the elements of an IE blob. Iteration stops at the first
+/// element whose header or body is truncated.
+pub fn elements(ies: &[u8]) -> Elements<'_> {
+    Elements { remaining: ies }
+}
+
+/// Returns the SSID, which is empty for hidden networks.
+pub fn find_ssid(ies: &[u8]) -> Option<&[u8]> {
+    elements(ies).find_map(|element| match element {
+        Element::Ssid(ssid) => Some(ssid),
+        _ => None,
+    })
+}
+
+/// Operating channel width of the access point in MHz, from its HT, VHT and
+/// HE operation elements. Access points without any of them use 20 MHz.
+pub fn operating_width_mhz(ies: &[u8]) -> u32 {
+    let mut ht_width = None;
+    let mut vht_width = None;
+    let mut he_6ghz_width = None;
+
+    for element in elements(ies) {
+        match element {
+            Element::HtOperation {
+                secondary_channel_offset,
+                any_channel_width,
+                ..
+            } => {
+                let is_40mhz = secondary_channel_offset != 0 && any_channel_width;
+                ht_width = Some(if is_40mhz { 40 } else { 20 });
+            }
+            Element::VhtOperation {
+                channel_width,
+                center_frequency_segment1,
+                ..
+            } => {
+                vht_width = match channel_width {
+                    // 160 and 80+80 MHz set the second segment since
+                    // 802.11-2016, older access points use widths 2 and 3
+                    1 if center_frequency_segment1 != 0 => Some(160),
+                    1 => Some(80),
+                    2 | 3 => Some(160),
+                    // 20 or 40 MHz as given by the HT operation
+                    _ => None,
+                };
+            }
+            Element::HeOperation(data) => he_6ghz_width = he_6ghz_width_mhz(data),
+            _ => {}
+        }
+    }
+
+    he_6ghz_width.or(vht_width).or(ht_width).unwrap_or(20)
+}
+
+/// Only access points on 6 GHz announce their width in the HE operation.
+fn he_6ghz_width_mhz(data: &[u8]) -> Option<u32> {
+    let params = u32::from_le_bytes([*data.first()?, *data.get(1)?, *data.get(2)?, 0]);
+
+    if params & HE_OPERATION_6GHZ_INFO_PRESENT == 0 {
+        return None;
+    }
+
+    // Parameters, BSS color and the basic HE-MCS and NSS set come first
+    let mut offset = 6;
+    if params & HE_OPERATION_VHT_INFO_PRESENT != 0 {
+        offset += 3;
+    }
+    if params & HE_OPERATION_CO_HOSTED_BSS != 0 {
+        offset += 1;
+    }
+
+    // The control field follows the primary channel
+    let width = match *data.get(offset + 1)? & 0x03 {
+        0 => 20,
+        1 => 40,
+        2 => 80,
+        _ => 160,
+    };
+
+    Some(width)
+}
+
+#[derive(Debug, Clone)]
+pub struct Elements<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Element<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&id, rest) = self.remaining.split_first()?;
+        let (&len, rest) = rest.split_first()?;
+
+        if rest.len() < len.into() {
+            self.remaining = &[];
+            return None;
+        }
+
+        let (data, rest) = rest.split_at(len.into());
+        self.remaining = rest;
+
+        Some(parse_element(id, data).unwrap_or(Element::Malformed { id, data }))
+    }
+}
+
+fn parse_element(id: u8, data: &[u8]) -> Option<Element<'_>> {
+    let element = match id {
+        EID_SSID => Element::Ssid(data),
+        EID_SUPPORTED_RATES => Element::SupportedRates(Rates(data)),
+        EID_EXTENDED_SUPPORTED_RATES => Element::ExtendedSupportedRates(Rates(data)),
+        EID_DS_PARAMETER_SET => Element::DsParameterSet {
+            channel: *data.first()?,
+        },
+        EID_COUNTRY => Element::Country {
+            code: data.get(..2)?.try_into().ok()?,
+            environment: *data.get(2)?,
+            triplets: &data[3..],
+        },
+        EID_BSS_LOAD => {
+            let mut reader = Reader(data);
+            Element::BssLoad {
+                station_count: reader.u16()?,
+                channel_utilization: reader.u8()?,
+                available_admission_capacity: reader.u16()?,
+            }
+        }
+        EID_RSN => Element::Rsn(Rsn::parse(data)?),
+        EID_HT_CAPABILITIES => Element::HtCapabilities {
+            capabilities_info: Reader(data).u16()?,
+            data,
+        },
+        EID_HT_OPERATION => {
+            let info = *data.get(1)?;
+            Element::HtOperation {
+                primary_channel: data[0],
+                secondary_channel_offset: info & 0x03,
+                any_channel_width: info & 0x04 != 0,
+                data,
+            }
+        }
+        EID_VHT_CAPABILITIES => Element::VhtCapabilities {
+            capabilities_info: Reader(data).u32()?,
+            data,
+        },
+        EID_VHT_OPERATION => {
+            let mut reader = Reader(data);
+            Element::VhtOperation {
+                channel_width: reader.u8()?,
+                center_frequency_segment0: reader.u8()?,
+                center_frequency_segment1: reader.u8()?,
+                data,
+            }
+        }
+        EID_EXTENDED_CAPABILITIES => Element::ExtendedCapabilities(ExtendedCapabilities(data)),
+        EID_EXTENSION => {
+            let (&ext_id, ext_data) = data.split_first()?;
+            match ext_id {
+                EID_EXT_HE_CAPABILITIES => Element::HeCapabilities(ext_data),
+                EID_EXT_HE_OPERATION => Element::HeOperation(ext_data),
+                _ => Element::Unknown { id, data },
+            }
+        }
+        EID_VENDOR_SPECIFIC => parse_vendor_specific(data)?,
+        _ => Element::Unknown { id, data },
+    };
+
+    Some(element)
+}
+
+fn parse_vendor_specific(data: &[u8]) -> Option<Element<'_>> {
+    let oui: [u8; 3] = data.get(..3)?.try_into().ok()?;
+    let data = &data[3..];
+
+    if oui != OUI_MICROSOFT {
+        return Some(Element::Vendor { oui, data });
+    }
+
+    let (&oui_type, data) = data.split_first()?;
+
+    let element = match oui_type {
+        MICROSOFT_TYPE_WPA => Element::Wpa(Rsn::parse(data)?),
+        MICROSOFT_TYPE_WPS => Element::Wps(data),
+        _ => Element::Microsoft { oui_type, data },
+    };
+
+    Some(element)
+}
+
+/// Supported rates in units of 500 kbit/s with the top bit marking basic
+/// rates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rates<'a>(pub &'a [u8]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedCapabilities<'a>(pub &'a [u8]);
+
+/// A cipher or AKM suite selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Suite {
+    pub oui: [u8; 3],
+    pub suite_type: u8,
+}
+
+impl Suite {
+    pub const AKM_8021X: Self = Self::ieee(1);
+    pub const AKM_PSK: Self = Self::ieee(2);
+    pub const AKM_FT_8021X: Self = Self::ieee(3);
+    pub const AKM_FT_PSK: Self = Self::ieee(4);
+    pub const AKM_8021X_SHA256: Self = Self::ieee(5);
+    pub const AKM_PSK_SHA256: Self = Self::ieee(6);
+    pub const AKM_SAE: Self = Self::ieee(8);
+    pub const AKM_FT_SAE: Self = Self::ieee(9);
+    pub const AKM_8021X_SUITE_B: Self = Self::ieee(11);
+    pub const AKM_8021X_SUITE_B_192: Self = Self::ieee(12);
+    pub const AKM_OWE: Self = Self::ieee(18);
+
+    const fn ieee(suite_type: u8) -> Self {
+        Self {
+            oui: OUI_IEEE_80211,
+            suite_type,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            oui: [bytes[0], bytes[1], bytes[2]],
+            suite_type: bytes[3],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuiteList<'a>(&'a [u8]);
+
+impl<'a> SuiteList<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = Suite> + 'a {
+        self.0.chunks_exact(4).map(Suite::from_bytes)
+    }
+
+    pub fn contains(&self, suite: Suite) -> bool {
+        self.iter().any(|s| s == suite)
+    }
+}
+
+/// The RSN element, also used for the body of the legacy WPA vendor
+/// element. Trailing fields are optional and absent when the element ends
+/// early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsn<'a> {
+    pub version: u16,
+    pub group_cipher: Option<Suite>,
+    pub pairwise_ciphers: SuiteList<'a>,
+    pub akm_suites: SuiteList<'a>,
+    pub capabilities: Option<u16>,
+}
+
+impl<'a> Rsn<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let mut reader = Reader(data);
+
+        let mut rsn = Self {
+            version: reader.u16()?,
+            group_cipher: None,
+            pairwise_ciphers: SuiteList(&[]),
+            akm_suites: SuiteList(&[]),
+            capabilities: None,
+        };
+
+        if reader.is_empty() {
+            return Some(rsn);
+        }
+        rsn.group_cipher = Some(Suite::from_bytes(reader.bytes(4)?));
+
+        if reader.is_empty() {
+            return Some(rsn);
+        }
+        rsn.pairwise_ciphers = reader.suite_list()?;
+
+        if reader.is_empty() {
+            return Some(rsn);
+        }
+        rsn.akm_suites = reader.suite_list()?;
+
+        if reader.is_empty() {
+            return Some(rsn);
+        }
+        rsn.capabilities = Some(reader.u16()?);
+
+        Some(rsn)
+    }
+}
+
+/// Little endian reader over an element body.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn suite_list(&mut self) -> Option<SuiteList<'a>> {
+        let count = usize::from(self.u16()?);
+        Some(SuiteList(self.bytes(count.checked_mul(4)?)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CIPHER_CCMP: Suite = Suite::ieee(4);
+
+    /// Beacon of a WPA2/WPA3 transition mode 5 GHz access point.
+    #[rustfmt::skip]
+    const BEACON_5GHZ: &[u8] = &[
+        // SSID "HomeNet"
+        0x00, 0x07, 0x48, 0x6f, 0x6d, 0x65, 0x4e, 0x65, 0x74,
+        // Supported rates 6(B), 9, 12(B), 18, 24(B), 36, 48, 54
+        0x01, 0x08, 0x8c, 0x12, 0x98, 0x24, 0xb0, 0x48, 0x60, 0x6c,
+        // Country "DE", all environments, channels 36-48 at 23 dBm
+        0x07, 0x06, 0x44, 0x45, 0x20, 0x24, 0x04, 0x17,
+        // BSS load: 3 stations, 18/255 utilization, capacity 0
+        0x0b, 0x05, 0x03, 0x00, 0x12, 0x00, 0x00,
+        // HT capabilities
+        0x2d, 0x1a, 0xef, 0x09, 0x1b, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // RSN: CCMP group and pairwise, PSK and SAE, MFP capable
+        0x30, 0x18, 0x01, 0x00, 0x00, 0x0f, 0xac, 0x04, 0x01, 0x00, 0x00, 0x0f, 0xac, 0x04,
+        0x02, 0x00, 0x00, 0x0f, 0xac, 0x02, 0x00, 0x0f, 0xac, 0x08, 0x80, 0x00,
+        // HT operation: primary channel 36, secondary above, any width
+        0x3d, 0x16, 0x24, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Extended capabilities with BSS transition (bit 19)
+        0x7f, 0x08, 0x04, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x40,
+        // VHT capabilities
+        0xbf, 0x0c, 0xb2, 0x79, 0x91, 0x33, 0xfa, 0xff, 0x0c, 0x03, 0xfa, 0xff, 0x0c, 0x03,
+        // VHT operation: 80 MHz centered on channel 42
+        0xc0, 0x05, 0x01, 0x2a, 0x00, 0xfc, 0xff,
+        // HE capabilities (extension)
+        0xff, 0x04, 0x23, 0x09, 0x01, 0x00,
+        // HE operation (extension)
+        0xff, 0x07, 0x24, 0xf4, 0x3f, 0x00, 0x19, 0xfc, 0xff,
+        // WMM
+        0xdd, 0x07, 0x00, 0x50, 0xf2, 0x02, 0x00, 0x01, 0x00,
+    ];
+
+    /// Beacon of a legacy WPA/WPS 2.4 GHz access point.
+    #[rustfmt::skip]
+    const BEACON_2GHZ: &[u8] = &[
+        // SSID "Cafe"
+        0x00, 0x04, 0x43, 0x61, 0x66, 0x65,
+        // Supported rates 1(B), 2(B), 5.5(B), 11(B)
+        0x01, 0x04, 0x82, 0x84, 0x8b, 0x96,
+        // DS parameter set: channel 6
+        0x03, 0x01, 0x06,
+        // Extended supported rates 24, 36, 48, 54
+        0x32, 0x04, 0x30, 0x48, 0x60, 0x6c,
+        // WPA: TKIP group and pairwise, PSK
+        0xdd, 0x16, 0x00, 0x50, 0xf2, 0x01, 0x01, 0x00, 0x00, 0x50, 0xf2, 0x02, 0x01, 0x00,
+        0x00, 0x50, 0xf2, 0x02, 0x01, 0x00, 0x00, 0x50, 0xf2, 0x02,
+        // WPS: version 1.0
+        0xdd, 0x09, 0x00, 0x50, 0xf2, 0x04, 0x10, 0x4a, 0x00, 0x01, 0x10,
+        // Vendor element of another OUI
+        0xdd, 0x05, 0x00, 0x10, 0x18, 0x02, 0x00,
+    ];
+
+    #[test]
+    fn parses_5ghz_beacon() {
+        let elements: Vec<_> = elements(BEACON_5GHZ).collect();
+
+        assert_eq!(elements.len(), 13);
+        assert_eq!(elements[0], Element::Ssid(b"HomeNet"));
+
+        assert_eq!(
+            elements[1],
+            Element::SupportedRates(Rates(&[0x8c, 0x12, 0x98, 0x24, 0xb0, 0x48, 0x60, 0x6c]))
+        );
+
+        assert_eq!(
+            elements[2],
+            Element::Country {
+                code: *b"DE",
+                environment: b' ',
+                triplets: &[0x24, 0x04, 0x17],
+            }
+        );
+        assert_eq!(
+            elements[3],
+            Element::BssLoad {
+                station_count: 3,
+                channel_utilization: 0x12,
+                available_admission_capacity: 0,
+            }
+        );
+        assert!(matches!(
+            elements[4],
+            Element::HtCapabilities {
+                capabilities_info: 0x09ef,
+                ..
+            }
+        ));
+
+        match elements[5] {
+            Element::Rsn(rsn) => {
+                assert_eq!(rsn.version, 1);
+                assert_eq!(rsn.group_cipher, Some(CIPHER_CCMP));
+                assert!(rsn.pairwise_ciphers.contains(CIPHER_CCMP));
+                assert!(rsn.akm_suites.contains(Suite::AKM_PSK));
+                assert!(rsn.akm_suites.contains(Suite::AKM_SAE));
+                assert_eq!(rsn.capabilities, Some(0x0080));
+            }
+            ref element => panic!("unexpected element {:?}", element),
+        }
+
+        assert!(matches!(
+            elements[6],
+            Element::HtOperation {
+                primary_channel: 36,
+                secondary_channel_offset: 1,
+                any_channel_width: true,
+                ..
+            }
+        ));
+
+        assert_eq!(
+            elements[7],
+            Element::ExtendedCapabilities(ExtendedCapabilities(&[
+                0x04, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x40
+            ]))
+        );
+
+        assert!(matches!(
+            elements[8],
+            Element::VhtCapabilities {
+                capabilities_info: 0x3391_79b2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            elements[9],
+            Element::VhtOperation {
+                channel_width: 1,
+                center_frequency_segment0: 42,
+                center_frequency_segment1: 0,
+                ..
+            }
+        ));
+        assert_eq!(elements[10], Element::HeCapabilities(&[0x09, 0x01, 0x00]));
+        assert!(matches!(elements[11], Element::HeOperation(data) if data.len() == 6));
+        assert_eq!(
+            elements[12],
+            Element::Microsoft {
+                oui_type: 2,
+                data: &[0x00, 0x01, 0x00],
+            }
+        );
+    }
+
+    #[test]
+    fn operating_width_from_operation_elements() {
+        assert_eq!(operating_width_mhz(BEACON_5GHZ), 80);
+        assert_eq!(operating_width_mhz(BEACON_2GHZ), 20);
+
+        #[rustfmt::skip]
+        let ht_40mhz = [
+            // HT operation: primary channel 6, secondary below, any width
+            0x3d, 0x16, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(operating_width_mhz(&ht_40mhz), 40);
+
+        // VHT operation: 160 MHz centered on channel 50
+        let vht_160mhz = [0xc0, 0x05, 0x01, 0x2a, 0x32, 0xfc, 0xff];
+        assert_eq!(operating_width_mhz(&vht_160mhz), 160);
+
+        #[rustfmt::skip]
+        let he_6ghz_80mhz = [
+            // HE operation with 6 GHz info: primary channel 37, 80 MHz
+            0xff, 0x0c, 0x24, 0x00, 0x00, 0x02, 0x01, 0xfc, 0xff,
+            0x25, 0x02, 0x27, 0x00, 0x00,
+        ];
+        assert_eq!(operating_width_mhz(&he_6ghz_80mhz), 80);
+    }
+
+    #[test]
+    fn parses_2ghz_beacon() {
+        let elements: Vec<_> = elements(BEACON_2GHZ).collect();
+
+        assert_eq!(elements.len(), 7);
+        assert_eq!(find_ssid(BEACON_2GHZ), Some(&b"Cafe"[..]));
+        assert_eq!(elements[2], Element::DsParameterSet { channel: 6 });
+
+        assert_eq!(
+            elements[3],
+            Element::ExtendedSupportedRates(Rates(&[0x30, 0x48, 0x60, 0x6c]))
+        );
+
+        match elements[4] {
+            Element::Wpa(wpa) => {
+                let tkip = Suite {
+                    oui: OUI_MICROSOFT,
+                    suite_type: 2,
+                };
+                assert_eq!(wpa.version, 1);
+                assert_eq!(wpa.group_cipher, Some(tkip));
+                assert_eq!(wpa.pairwise_ciphers.iter().collect::<Vec<_>>(), [tkip]);
+                assert_eq!(wpa.akm_suites.iter().count(), 1);
+                assert_eq!(wpa.capabilities, None);
+            }
+            ref element => panic!("unexpected element {:?}", element),
+        }
+
+        assert_eq!(elements[5], Element::Wps(&[0x10, 0x4a, 0x00, 0x01, 0x10]));
+        assert_eq!(
+            elements[6],
+            Element::Vendor {
+                oui: [0x00, 0x10, 0x18],
+                data: &[0x02, 0x00],
+            }
+        );
+    }
+
+    #[test]
+    fn hidden_ssid_is_empty() {
+        assert_eq!(find_ssid(&[0x00, 0x00, 0x03, 0x01, 0x0b]), Some(&[][..]));
+        assert_eq!(find_ssid(&[0x03, 0x01, 0x0b]), None);
+    }
+
+    #[test]
+    fn stops_at_truncated_element() {
+        // The second element claims 8 bytes but only 3 follow
+        let ies = [0x00, 0x02, 0x41, 0x42, 0x01, 0x08, 0x82, 0x84, 0x8b];
+        let elements: Vec<_> = elements(&ies).collect();
+
+        assert_eq!(elements, [Element::Ssid(b"AB")]);
+    }
+
+    #[test]
+    fn stops_at_truncated_header() {
+        assert_eq!(elements(&[]).count(), 0);
+        assert_eq!(elements(&[0x00]).count(), 0);
+        assert_eq!(elements(&[0x03, 0x01, 0x06, 0x30]).count(), 1);
+    }
+
+    #[test]
+    fn reports_malformed_elements() {
+        #[rustfmt::skip]
+        let ies = [
+            // Empty DS parameter set
+            0x03, 0x00,
+            // BSS load missing the admission capacity
+            0x0b, 0x03, 0x01, 0x00, 0x10,
+            // RSN cut inside the pairwise suite list
+            0x30, 0x0a, 0x01, 0x00, 0x00, 0x0f, 0xac, 0x04, 0x02, 0x00, 0x00, 0x0f,
+            // Vendor element shorter than an OUI
+            0xdd, 0x02, 0x00, 0x50,
+            // Country element without environment
+            0x07, 0x02, 0x55, 0x53,
+        ];
+        let ids: Vec<_> = elements(&ies)
+            .map(|element| match element {
+                Element::Malformed { id, .. } => id,
+                element => panic!("unexpected element {:?}", element),
+            })
+            .collect();
+
+        assert_eq!(ids, [3, 11, 48, 221, 7]);
+    }
+
+    #[test]
+    fn rsn_trailing_fields_are_optional() {
+        let ies = [0x30, 0x02, 0x01, 0x00];
+
+        match elements(&ies).next() {
+            Some(Element::Rsn(rsn)) => {
+                assert_eq!(rsn.version, 1);
+                assert_eq!(rsn.group_cipher, None);
+                assert_eq!(rsn.pairwise_ciphers.iter().count(), 0);
+                assert_eq!(rsn.akm_suites.iter().count(), 0);
+                assert_eq!(rsn.capabilities, None);
+            }
+            element => panic!("unexpected element {:?}", element),
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_elements() {
+        let ies = [0x2a, 0x01, 0x00, 0xff, 0x02, 0x6c, 0x00];
+        let elements: Vec<_> = elements(&ies).collect();
+
+        assert_eq!(
+            elements,
+            [
+                Element::Unknown {
+                    id: 42,
+                    data: &[0x00],
+                },
+                Element::Unknown {
+                    id: 255,
+                    data: &[0x6c, 0x00],
+                },
+            ]
+        );
+    }
+}
@@ -2,14 +2,22 @@
 
 use std::convert::{TryFrom, TryInto};
 
+use anyhow::{Context, Result};
+
 use macaddr::MacAddr6;
 
+use neli::consts::nl::{NlmF, NlmFFlags};
 use neli::genl::Genlmsghdr;
+use neli::nl::{NlPayload, Nlmsghdr};
+use neli::types::{Buffer, GenlBuffer};
+
+use serde::Serialize;
 
 use crate::nl80211::consts;
 use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd};
+use crate::nl80211::session::Nl80211Session;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum InterfaceType {
     Unspecified = 0,
     Adhoc,
@@ -84,3 +92,30 @@ impl TryFrom<&Genlmsghdr<Nl80211Cmd, Nl80211Attr>> for Interface {
         })
     }
 }
+
+pub(super) async fn get_interfaces(session: &Nl80211Session) -> Result<Vec<Interface>> {
+    let nl_msghdr = create_get_interface_message(session.nl_id());
+
+    session
+        .dump(nl_msghdr, |payload| Interface::try_from(&payload).ok())
+        .await
+        .context("Failed to receive get interface response")
+}
+
+/// Looks up a wireless interface by name.
+pub(super) async fn find_interface(session: &Nl80211Session, name: &str) -> Result<Interface> {
+    get_interfaces(session)
+        .await
+        .context("Failed to get interfaces")?
+        .into_iter()
+        .find(|iface| iface.name == name)
+        .context(format!("Interface '{}' not found", name))
+}
+
+fn create_get_interface_message(nl_id: u16) -> Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>> {
+    let attrs = GenlBuffer::<Nl80211Attr, Buffer>::new();
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::GetInterface, 1, attrs);
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Nlmsghdr::new(None, nl_id, flags, None, None, payload)
+}
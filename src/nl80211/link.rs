@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+
+use macaddr::MacAddr6;
+
+use serde::Serialize;
+
+use crate::nl80211::bss::{serialize_mac, Band};
+use crate::nl80211::interface::find_interface;
+use crate::nl80211::scan::get_scan_results;
+use crate::nl80211::session::Nl80211Session;
+use crate::nl80211::station::{get_stations, RateInfo};
+
+/// Link of a station interface, `link` is empty while not associated.
+#[derive(Serialize, Debug, Clone)]
+pub struct LinkStatus {
+    pub connected: bool,
+    #[serde(flatten)]
+    pub link: Option<Link>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Link {
+    pub ssid: String,
+    #[serde(serialize_with = "serialize_mac")]
+    pub bssid: MacAddr6,
+    /// Center frequency in MHz
+    pub frequency: u32,
+    pub channel: Option<u32>,
+    pub band: Option<Band>,
+    pub signal_dbm: i32,
+    pub tx_rate: Option<RateInfo>,
+    pub rx_rate: Option<RateInfo>,
+    pub connected_secs: Option<u32>,
+}
+
+/// Reports the access point a station interface is associated with, along
+/// with the quality of the link to it.
+pub async fn get_link(session: &Nl80211Session, interface: &str) -> Result<LinkStatus> {
+    let iface = find_interface(session, interface).await?;
+
+    let bss = get_scan_results(session, iface.index)
+        .await
+        .context("Failed to get scan results")?
+        .into_iter()
+        .find(|bss| bss.associated);
+
+    let bss = match bss {
+        Some(bss) => bss,
+        None => {
+            return Ok(LinkStatus {
+                connected: false,
+                link: None,
+            })
+        }
+    };
+
+    // In station mode the only station is the access point
+    let station = get_stations(session, iface.index)
+        .await
+        .context("Failed to get stations")?
+        .into_iter()
+        .find(|station| station.mac == bss.bssid);
+
+    let link = Link {
+        ssid: bss.ssid,
+        bssid: bss.bssid,
+        frequency: bss.frequency,
+        channel: bss.channel,
+        band: bss.band,
+        signal_dbm: station
+            .as_ref()
+            .and_then(|station| station.signal_dbm)
+            .map_or(bss.signal_dbm, i32::from),
+        tx_rate: station.as_ref().and_then(|station| station.tx_rate),
+        rx_rate: station.as_ref().and_then(|station| station.rx_rate),
+        connected_secs: station.and_then(|station| station.connected_secs),
+    };
+
+    Ok(LinkStatus {
+        connected: true,
+        link: Some(link),
+    })
+}
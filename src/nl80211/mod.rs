@@ -1,6 +1,16 @@
 mod enums;
 mod interface;
+mod request;
 
+pub mod bss;
 #[allow(dead_code, non_upper_case_globals, non_camel_case_types)]
 mod consts;
+pub mod error;
+pub mod ie;
+pub mod link;
 pub mod scan;
+pub mod session;
+pub mod station;
+pub mod survey;
+pub mod virtual_interface;
+pub mod wiphy;
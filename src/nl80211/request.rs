@@ -0,0 +1,383 @@
+use std::io::Cursor;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+
+use anyhow::{bail, Context, Result};
+
+use neli::consts::nl::Nlmsg;
+use neli::consts::socket::NlFamily;
+use neli::genl::Genlmsghdr;
+use neli::socket::tokio::NlSocket;
+use neli::socket::NlSocketHandle;
+use neli::FromBytesWithInput;
+
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd};
+use crate::nl80211::error::Nl80211Error;
+
+const NL80211_FAMILY_NAME: &str = "nl80211";
+const SCAN_MULTICAST_NAME: &str = "scan";
+
+// Not exported by all versions of the libc crate
+const NETLINK_CAP_ACK: libc::c_int = 10;
+const NETLINK_EXT_ACK: libc::c_int = 11;
+
+/// Error flag telling that the failed request is not echoed back
+const NLM_F_CAPPED: u16 = 0x100;
+/// Error flag telling that extended acknowledgement attributes follow
+const NLM_F_ACK_TLVS: u16 = 0x200;
+
+const NLMSGERR_ATTR_MSG: u16 = 1;
+
+/// Types below this are netlink control messages
+const NLMSG_MIN_TYPE: u16 = 0x10;
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSGERR_LEN: usize = 4 + NLMSG_HDRLEN;
+const NLA_HDRLEN: usize = 4;
+
+/// Message received from nl80211, with acknowledgements and errors decoded.
+pub(super) struct NlMessage {
+    pub seq: u32,
+    pub kind: NlMessageKind,
+}
+
+pub(super) enum NlMessageKind {
+    /// Generic netlink message, see `parse_genl_payload`
+    Payload(Vec<u8>),
+    /// End of a dump
+    Done,
+    Ack,
+    Err(Nl80211Error),
+}
+
+pub(super) fn create_main_socket() -> Result<(NlSocket, u16)> {
+    let mut socket_handle = NlSocketHandle::connect(NlFamily::Generic, None, &[])
+        .context("Failed to establish netlink socket")?;
+
+    enable_extended_ack(&socket_handle)?;
+
+    let nl_id = socket_handle
+        .resolve_genl_family(NL80211_FAMILY_NAME)
+        .context("Failed to resolve nl80211 family")?;
+
+    let socket = NlSocket::new(socket_handle).context("Failed to connect main socket")?;
+
+    Ok((socket, nl_id))
+}
+
+pub(super) fn create_multicast_socket() -> Result<NlSocket> {
+    let mut socket_handle_mcast = NlSocketHandle::connect(NlFamily::Generic, None, &[])
+        .context("Failed to connect multicast socket")?;
+
+    let mcast_id = socket_handle_mcast
+        .resolve_nl_mcast_group(NL80211_FAMILY_NAME, SCAN_MULTICAST_NAME)
+        .context("Failed to resolve muticast group")?;
+    socket_handle_mcast
+        .add_mcast_membership(&[mcast_id])
+        .context("Failed to add multicast membership")?;
+
+    NlSocket::new(socket_handle_mcast).context("Failed to set up multicast socket")
+}
+
+/// Asks the kernel to explain why a request failed instead of echoing the
+/// request back.
+fn enable_extended_ack(socket_handle: &NlSocketHandle) -> Result<()> {
+    let enable: libc::c_int = 1;
+
+    for option in [NETLINK_CAP_ACK, NETLINK_EXT_ACK] {
+        let result = unsafe {
+            libc::setsockopt(
+                socket_handle.as_raw_fd(),
+                libc::SOL_NETLINK,
+                option,
+                (&enable as *const libc::c_int).cast(),
+                size_of::<libc::c_int>().try_into()?,
+            )
+        };
+
+        if result != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("Failed to enable extended acknowledgements");
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn parse_genl_payload(bytes: &[u8]) -> Result<Genlmsghdr<Nl80211Cmd, Nl80211Attr>> {
+    Genlmsghdr::from_bytes_with_input(&mut Cursor::new(bytes), bytes.len())
+        .context("Failed to parse nl80211 message")
+}
+
+/// Splits a received datagram into its messages. neli cannot parse
+/// acknowledgements followed by extended acknowledgement attributes, which
+/// the kernel also sends on success, so they are decoded here.
+pub(super) fn parse_messages(mut bytes: &[u8]) -> Result<Vec<NlMessage>> {
+    let mut messages = Vec::new();
+
+    while !bytes.is_empty() {
+        let header = bytes
+            .get(..NLMSG_HDRLEN)
+            .context("Truncated netlink message header")?;
+
+        let len = usize::try_from(read_u32(header, 0).unwrap_or_default())?;
+        let nl_type = read_u16(header, 4).unwrap_or_default();
+        let flags = read_u16(header, 6).unwrap_or_default();
+        let seq = read_u32(header, 8).unwrap_or_default();
+
+        if len < NLMSG_HDRLEN {
+            bail!("Invalid netlink message length {}", len);
+        }
+
+        let payload = bytes
+            .get(NLMSG_HDRLEN..len)
+            .context("Truncated netlink message")?;
+
+        let kind = if nl_type == u16::from(Nlmsg::Done) {
+            Some(NlMessageKind::Done)
+        } else if nl_type == u16::from(Nlmsg::Error) {
+            if payload.len() < NLMSGERR_LEN {
+                bail!("Truncated netlink error message");
+            }
+
+            match read_i32(payload, 0).unwrap_or_default() {
+                0 => Some(NlMessageKind::Ack),
+                error => Some(NlMessageKind::Err(decode_error(
+                    error,
+                    flags,
+                    &payload[4..],
+                ))),
+            }
+        } else if nl_type >= NLMSG_MIN_TYPE {
+            Some(NlMessageKind::Payload(payload.to_vec()))
+        } else {
+            None
+        };
+
+        if let Some(kind) = kind {
+            messages.push(NlMessage { seq, kind });
+        }
+
+        // Messages are padded to 4 bytes
+        let aligned_len = (len + 3) & !3;
+        bytes = bytes.get(aligned_len..).unwrap_or_default();
+    }
+
+    Ok(messages)
+}
+
+/// Decodes an error from the header of the failed request that the kernel
+/// echoes back, and what follows it.
+fn decode_error(error: i32, flags: u16, echoed: &[u8]) -> Nl80211Error {
+    // Without `NETLINK_CAP_ACK` the payload of the failed request comes
+    // first
+    let echoed_len = if flags & NLM_F_CAPPED == 0 {
+        read_u32(echoed, 0)
+            .and_then(|len| usize::try_from(len).ok())
+            .unwrap_or_default()
+            .max(NLMSG_HDRLEN)
+    } else {
+        NLMSG_HDRLEN
+    };
+
+    let message = if flags & NLM_F_ACK_TLVS != 0 {
+        find_ext_ack_message(echoed.get(echoed_len..).unwrap_or_default())
+    } else {
+        None
+    };
+
+    Nl80211Error::new(-error, message)
+}
+
+fn find_ext_ack_message(mut attrs: &[u8]) -> Option<String> {
+    while attrs.len() >= NLA_HDRLEN {
+        let len = usize::from(u16::from_ne_bytes([attrs[0], attrs[1]]));
+        let attr_type = u16::from_ne_bytes([attrs[2], attrs[3]]);
+
+        if len < NLA_HDRLEN || len > attrs.len() {
+            return None;
+        }
+
+        if attr_type == NLMSGERR_ATTR_MSG {
+            let value = &attrs[NLA_HDRLEN..len];
+            let message = value.split(|byte| *byte == 0).next().unwrap_or_default();
+            return Some(String::from_utf8_lossy(message).into_owned());
+        }
+
+        // Attributes are padded to 4 bytes
+        let aligned_len = (len + 3) & !3;
+        attrs = attrs.get(aligned_len..).unwrap_or_default();
+    }
+
+    None
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?;
+    Some(u16::from_ne_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> Option<i32> {
+    read_u32(bytes, offset).map(|value| i32::from_ne_bytes(value.to_ne_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NLMSGERR_ATTR_OFFS: u16 = 2;
+
+    fn header(len: usize, nl_type: u16, flags: u16, seq: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&u32::try_from(len).unwrap().to_ne_bytes());
+        bytes.extend_from_slice(&nl_type.to_ne_bytes());
+        bytes.extend_from_slice(&flags.to_ne_bytes());
+        bytes.extend_from_slice(&seq.to_ne_bytes());
+        bytes.extend_from_slice(&0_u32.to_ne_bytes());
+        bytes
+    }
+
+    /// Attribute with its length field set to `len`, padded to 4 bytes.
+    fn attr(len: usize, attr_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&u16::try_from(len).unwrap().to_ne_bytes());
+        bytes.extend_from_slice(&attr_type.to_ne_bytes());
+        bytes.extend_from_slice(value);
+        bytes.resize((bytes.len() + 3) & !3, 0);
+        bytes
+    }
+
+    fn msg_attr(message: &str) -> Vec<u8> {
+        let value = [message.as_bytes(), &[0]].concat();
+        attr(NLA_HDRLEN + value.len(), NLMSGERR_ATTR_MSG, &value)
+    }
+
+    fn invalid_argument(message: &str) -> Nl80211Error {
+        Nl80211Error::new(libc::EINVAL, Some(message.to_owned()))
+    }
+
+    #[test]
+    fn decode_capped_error() {
+        let echoed = [header(36, 0x1c, 0x5, 7), msg_attr("invalid channel")].concat();
+
+        let err = decode_error(-libc::EINVAL, NLM_F_CAPPED | NLM_F_ACK_TLVS, &echoed);
+
+        assert_eq!(err, invalid_argument("invalid channel"));
+    }
+
+    #[test]
+    fn decode_echoed_error() {
+        let request = [0x22, 0x01, 0x00, 0x00, 0x08, 0x00, 0x03, 0x00];
+        let echoed = [
+            header(NLMSG_HDRLEN + request.len(), 0x1c, 0x5, 7),
+            request.to_vec(),
+            msg_attr("invalid channel"),
+        ]
+        .concat();
+
+        let err = decode_error(-libc::EINVAL, NLM_F_ACK_TLVS, &echoed);
+
+        assert_eq!(err, invalid_argument("invalid channel"));
+    }
+
+    #[test]
+    fn decode_error_without_attributes() {
+        let echoed = [header(16, 0x1c, 0x5, 7), msg_attr("ignored")].concat();
+
+        let err = decode_error(-libc::EBUSY, NLM_F_CAPPED, &echoed);
+        assert_eq!(err, Nl80211Error::new(libc::EBUSY, None));
+
+        let err = decode_error(-libc::EBUSY, NLM_F_CAPPED | NLM_F_ACK_TLVS, &echoed[..16]);
+        assert_eq!(err, Nl80211Error::new(libc::EBUSY, None));
+
+        // Echoed request longer than the message
+        let err = decode_error(-libc::EBUSY, NLM_F_ACK_TLVS, &header(64, 0x1c, 0x5, 7));
+        assert_eq!(err, Nl80211Error::new(libc::EBUSY, None));
+    }
+
+    #[test]
+    fn find_message_after_unpadded_attribute() {
+        let attrs = [attr(5, NLMSGERR_ATTR_OFFS, &[0x24]), msg_attr("bad")].concat();
+        assert_eq!(find_ext_ack_message(&attrs).as_deref(), Some("bad"));
+
+        // Neither terminated nor padded
+        let attrs = [0x07, 0x00, 0x01, 0x00, b'b', b'a', b'd'];
+        assert_eq!(find_ext_ack_message(&attrs).as_deref(), Some("bad"));
+    }
+
+    #[test]
+    fn find_message_rejects_invalid_lengths() {
+        let attrs = attr(64, NLMSGERR_ATTR_MSG, b"bad\0");
+        assert_eq!(find_ext_ack_message(&attrs), None);
+
+        let attrs = [attr(2, NLMSGERR_ATTR_OFFS, &[]), msg_attr("bad")].concat();
+        assert_eq!(find_ext_ack_message(&attrs), None);
+
+        assert_eq!(find_ext_ack_message(&[0x08, 0x00]), None);
+    }
+
+    #[test]
+    fn parse_acknowledgement_with_attributes() {
+        let payload = [
+            0_i32.to_ne_bytes().to_vec(),
+            header(36, 0x1c, 0x5, 7),
+            msg_attr("deprecated"),
+        ]
+        .concat();
+        let flags = NLM_F_CAPPED | NLM_F_ACK_TLVS;
+        let bytes = [header(NLMSG_HDRLEN + payload.len(), 2, flags, 7), payload].concat();
+
+        let messages = parse_messages(&bytes).unwrap();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].seq, 7);
+        assert!(matches!(messages[0].kind, NlMessageKind::Ack));
+    }
+
+    #[test]
+    fn parse_error_after_payload() {
+        let genl = [0x22, 0x01, 0x00, 0x00, 0x05, 0x00, 0x03, 0x00, 0x01];
+        let payload = [
+            (-libc::EINVAL).to_ne_bytes().to_vec(),
+            header(16, 0x1c, 0x5, 7),
+            msg_attr("invalid channel"),
+        ]
+        .concat();
+        let bytes = [
+            header(NLMSG_HDRLEN + genl.len(), 0x1c, 0, 6),
+            genl.to_vec(),
+            vec![0; 3],
+            header(
+                NLMSG_HDRLEN + payload.len(),
+                2,
+                NLM_F_CAPPED | NLM_F_ACK_TLVS,
+                7,
+            ),
+            payload,
+        ]
+        .concat();
+
+        let messages = parse_messages(&bytes).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert!(
+            matches!(messages[0].kind, NlMessageKind::Payload(ref payload) if payload == &genl)
+        );
+        assert!(
+            matches!(messages[1].kind, NlMessageKind::Err(ref err) if *err == invalid_argument("invalid channel"))
+        );
+    }
+
+    #[test]
+    fn parse_truncated_messages() {
+        assert!(parse_messages(&header(16, 3, 0, 1)[..12]).is_err());
+        assert!(parse_messages(&header(24, 0x1c, 0, 1)).is_err());
+        assert!(parse_messages(&header(8, 0x1c, 0, 1)).is_err());
+        assert!(parse_messages(&[header(20, 2, 0, 1), vec![0; 4]].concat()).is_err());
+    }
+}
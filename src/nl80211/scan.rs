@@ -1,199 +1,346 @@
-use std::io::Cursor;
-use std::io::Read;
+use std::fmt;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
 
-use byteorder::ReadBytesExt;
-
-use neli::attr::Attribute;
-use neli::consts::nl::{NlmF, NlmFFlags, Nlmsg};
-use neli::consts::socket::NlFamily;
-use neli::consts::MAX_NL_LENGTH;
+use neli::consts::nl::{NlmF, NlmFFlags};
 use neli::genl::{Genlmsghdr, Nlattr};
 use neli::nl::{NlPayload, Nlmsghdr};
-use neli::socket::tokio::NlSocket;
-use neli::socket::NlSocketHandle;
-use neli::types::{Buffer, GenlBuffer};
+use neli::types::Buffer;
+
+use tokio::sync::broadcast;
+
+use crate::error::InvalidRequest;
+use crate::nl80211::bss::{Band, Bss};
+use crate::nl80211::consts;
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd};
+use crate::nl80211::error::{ErrorKind, Nl80211Error};
+use crate::nl80211::interface::{find_interface, Interface};
+use crate::nl80211::session::{Nl80211Session, ScanEvent};
+use crate::nl80211::wiphy::{dump_wiphy, Wiphy};
+
+const SCAN_ATTEMPTS: usize = 3;
+
+/// Scan failures reported after the scan was triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanError {
+    /// The kernel aborted the scan, e.g. because the interface went down or
+    /// started connecting. Scanning again usually succeeds.
+    Aborted,
+    /// No scan results arrived in time
+    TimedOut(Duration),
+}
 
-use crate::network::Station;
-use crate::nl80211::consts::NL80211_SCAN_FLAG_AP;
-use crate::nl80211::enums::{Nl80211Attr, Nl80211Bss, Nl80211Cmd};
-use crate::nl80211::interface::Interface;
+impl ScanError {
+    pub fn is_retryable(self) -> bool {
+        self == Self::Aborted
+    }
+}
 
-const NL80211_FAMILY_NAME: &str = "nl80211";
-const SCAN_MULTICAST_NAME: &str = "scan";
-const WLAN_EID_SSID: u8 = 0;
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Aborted => write!(f, "Scan aborted"),
+            Self::TimedOut(timeout) => {
+                write!(f, "Scan not completed within {} s", timeout.as_secs())
+            }
+        }
+    }
+}
 
-pub async fn scan(interface: &str) -> Result<Vec<Station>> {
-    let (mut socket, nl_id) = create_main_socket()?;
+impl std::error::Error for ScanError {}
+
+/// Restricts the channels and the way they are scanned. The default is an
+/// active scan of every channel supported by the radio.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Hidden networks to probe for, they only answer probes for their name
+    pub ssids: Vec<String>,
+    /// Frequencies in MHz to scan
+    pub frequencies: Vec<u32>,
+    /// Bands to scan in addition to `frequencies`
+    pub bands: Vec<Band>,
+    /// Only listen for beacons instead of sending probe requests
+    pub passive: bool,
+    /// Let traffic of the access point take precedence over the scan
+    pub low_priority: bool,
+    /// Scan fewer channels and return more quickly
+    pub low_span: bool,
+    /// Send probe requests from a random MAC address
+    pub random_mac: bool,
+}
 
-    let ifaces = get_interfaces(&mut socket, nl_id)
-        .await
-        .context("Failed to get interfaces")?;
+impl ScanOptions {
+    pub fn with_ssids(ssids: Vec<String>) -> Self {
+        Self {
+            ssids,
+            ..Self::default()
+        }
+    }
 
-    let iface = ifaces
-        .iter()
-        .find(|iface| iface.name == interface)
-        .context("Interface not found")?;
+    fn needs_wiphy(&self) -> bool {
+        !self.bands.is_empty() || self.low_priority || self.low_span || self.random_mac
+    }
+}
 
-    trigger_scan(&mut socket, nl_id, iface.index)
-        .await
-        .context("Failed to trigger scan")?;
+/// Scans for access points with the given options.
+pub async fn scan(
+    session: &Nl80211Session,
+    interface: &str,
+    options: &ScanOptions,
+    timeout: Duration,
+) -> Result<Vec<Bss>> {
+    if options.passive && !options.ssids.is_empty() {
+        return Err(
+            InvalidRequest::new("Hidden networks cannot be found by a passive scan").into(),
+        );
+    }
 
-    let mut socket_mcast = create_multicast_socket()?;
+    let iface = find_interface(session, interface).await?;
 
-    complete_scan(&mut socket_mcast).await?;
+    let wiphy = if options.needs_wiphy() {
+        dump_wiphy(session, iface.wiphy)
+            .await
+            .context("Failed to get wiphy")?
+    } else {
+        Wiphy::default()
+    };
 
-    get_scan_results(&mut socket, nl_id, iface.index).await
-}
+    let frequencies = scan_frequencies(options, &wiphy)?;
+    let flags = scan_flags(options, &wiphy);
 
-async fn get_interfaces(socket: &mut NlSocket, nl_id: u16) -> Result<Vec<Interface>> {
-    let nl_msghdr = create_get_interface_message(nl_id);
+    let mut attempt = 1;
 
-    socket
-        .send(&nl_msghdr)
-        .await
-        .expect("Failed to send get interface message");
+    loop {
+        // Subscribe before triggering, a short scan may otherwise complete
+        // before we listen for its results
+        let mut events = session.subscribe_scan_events();
 
-    recv_all(socket, |msg| {
-        Interface::try_from(msg.get_payload().ok()?).ok()
-    })
-    .await
-    .context("Failed to receive get interface response")
-}
+        let trigger = trigger_scan(session, iface.index, options, &frequencies, flags);
 
-async fn trigger_scan(socket: &mut NlSocket, nl_id: u16, iface_index: u32) -> Result<()> {
-    let nl_msghdr = create_trigger_scan_message(nl_id, iface_index)?;
+        if let Err(err) = trigger.await {
+            if !is_busy(&err) {
+                return Err(err.context("Failed to trigger scan"));
+            }
 
-    socket
-        .send(&nl_msghdr)
-        .await
-        .context("Failed to send trigger scan message")?;
+            // Another scan or an ongoing connection attempt holds the radio,
+            // the results of the last scan are still better than nothing
+            println!("Interface {} is busy, using cached scan results", interface);
 
-    let mut buf = vec![0; MAX_NL_LENGTH];
+            return get_scan_results(session, iface.index).await;
+        }
 
-    socket
-        .recv::<Nlmsg, Buffer>(&mut buf)
-        .await
-        .context("Failed to receive trigger scan acknowledgement")?;
+        let result = tokio::time::timeout(timeout, complete_scan(&mut events, &iface))
+            .await
+            .unwrap_or_else(|_| Err(ScanError::TimedOut(timeout).into()));
+
+        match result {
+            Ok(()) => break,
+            Err(err) if attempt < SCAN_ATTEMPTS && is_retryable(&err) => {
+                println!("Scan on {} failed: {}, retrying", interface, err);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
 
-    Ok(())
+    get_scan_results(session, iface.index).await
 }
 
-async fn complete_scan(socket_mcast: &mut NlSocket) -> Result<()> {
-    let mut buf = vec![0; MAX_NL_LENGTH];
-    let msgs = socket_mcast
-        .recv::<Nlmsg, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>(&mut buf)
-        .await
-        .context("Failed to receive new scan results notification")?;
+/// Requested frequencies and the frequencies of the requested bands, empty
+/// for all channels.
+fn scan_frequencies(options: &ScanOptions, wiphy: &Wiphy) -> Result<Vec<u32>> {
+    let mut frequencies = options.frequencies.clone();
 
-    let has_scan_results = msgs
-        .iter()
-        .filter_map(|nl_msghdr| nl_msghdr.get_payload().ok())
-        .any(|payload| payload.cmd == Nl80211Cmd::NewScanResults);
+    for band in &options.bands {
+        let band_frequencies = wiphy.band_frequencies(*band);
 
-    if !has_scan_results {
-        bail!("No scan results received");
-    }
+        if band_frequencies.is_empty() {
+            return Err(InvalidRequest::new(format!(
+                "Band {} is not supported by {}",
+                band, wiphy.name
+            ))
+            .into());
+        }
 
-    Ok(())
-}
+        frequencies.extend(band_frequencies);
+    }
 
-async fn get_scan_results(
-    socket: &mut NlSocket,
-    nl_id: u16,
-    iface_index: u32,
-) -> Result<Vec<Station>> {
-    let nl_msghdr = create_get_scan_message(nl_id, iface_index);
+    frequencies.sort_unstable();
+    frequencies.dedup();
 
-    socket
-        .send(&nl_msghdr)
-        .await
-        .context("Failed to send get scan results message")?;
-
-    recv_all(socket, |msg| {
-        let payload = msg.get_payload().ok()?;
-        let mut attrs = payload.get_attr_handle();
-        let bss_attrs = attrs
-            .get_nested_attributes::<Nl80211Bss>(Nl80211Attr::Bss)
-            .ok()?;
-
-        let signal_mbm = bss_attrs
-            .get_attribute(Nl80211Bss::SignalMbm)?
-            .get_payload_as::<i32>()
-            .ok()?;
-
-        let quality = dbm_level_to_quality(signal_mbm);
-
-        let ie_attrs = bss_attrs.get_attribute(Nl80211Bss::InformationElements)?;
-
-        let buffer = ie_attrs.payload();
-        let mut cursor = Cursor::new(buffer.as_ref());
-        let ssid_bytes = extract_ssid(&mut cursor);
-        let ssid = String::from_utf8(ssid_bytes)
-            .ok()
-            .filter(|s| !s.is_empty())?;
-
-        Some(Station { ssid, quality })
-    })
-    .await
-    .context("Failed to receive get scan results response")
+    Ok(frequencies)
 }
 
-fn create_main_socket() -> Result<(NlSocket, u16)> {
-    let mut socket_handle = NlSocketHandle::connect(NlFamily::Generic, None, &[])
-        .context("Failed to establish netlink socket")?;
+/// Flags not supported by the driver are left out, as the kernel would
+/// reject the whole scan otherwise.
+fn scan_flags(options: &ScanOptions, wiphy: &Wiphy) -> u32 {
+    let mut flags = consts::NL80211_SCAN_FLAG_AP;
+
+    let optional_flags = [
+        (
+            options.low_priority,
+            wiphy.scan_low_priority,
+            consts::NL80211_SCAN_FLAG_LOW_PRIORITY,
+            "Low priority scans",
+        ),
+        (
+            options.low_span,
+            wiphy.scan_low_span,
+            consts::NL80211_SCAN_FLAG_LOW_SPAN,
+            "Low span scans",
+        ),
+        (
+            options.random_mac,
+            wiphy.scan_random_mac,
+            consts::NL80211_SCAN_FLAG_RANDOM_ADDR,
+            "Random MAC address scans",
+        ),
+    ];
+
+    for (requested, supported, flag, name) in optional_flags {
+        if !requested {
+            continue;
+        }
 
-    let nl_id = socket_handle
-        .resolve_genl_family(NL80211_FAMILY_NAME)
-        .context("Failed to resolve nl80211 family")?;
+        if supported {
+            flags |= flag;
+        } else {
+            println!("{} are not supported by {}, ignoring", name, wiphy.name);
+        }
+    }
 
-    let socket = NlSocket::new(socket_handle).context("Failed to connect main socket")?;
+    flags
+}
 
-    Ok((socket, nl_id))
+async fn trigger_scan(
+    session: &Nl80211Session,
+    iface_index: u32,
+    options: &ScanOptions,
+    frequencies: &[u32],
+    flags: u32,
+) -> Result<()> {
+    let nl_msghdr =
+        create_trigger_scan_message(session.nl_id(), iface_index, options, frequencies, flags)?;
+
+    session.ack(nl_msghdr).await
 }
 
-fn create_multicast_socket() -> Result<NlSocket> {
-    let mut socket_handle_mcast = NlSocketHandle::connect(NlFamily::Generic, None, &[])
-        .context("Failed to connect multicast socket")?;
+fn is_busy(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Nl80211Error>()
+        .map_or(false, |err| err.kind() == ErrorKind::Busy)
+}
 
-    let mcast_id = socket_handle_mcast
-        .resolve_nl_mcast_group(NL80211_FAMILY_NAME, SCAN_MULTICAST_NAME)
-        .context("Failed to resolve muticast group")?;
-    socket_handle_mcast
-        .add_mcast_membership(&[mcast_id])
-        .context("Failed to add multicast membership")?;
+fn is_retryable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<ScanError>()
+        .map_or(false, |err| err.is_retryable())
+}
 
-    NlSocket::new(socket_handle_mcast).context("Failed to set up multicast socket")
+/// Waits for the scan on `iface` to finish. The multicast group carries the
+/// notifications of all interfaces, so those of other interfaces are skipped.
+async fn complete_scan(
+    events: &mut broadcast::Receiver<ScanEvent>,
+    iface: &Interface,
+) -> Result<()> {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // Missed notifications may have been for other interfaces, ours
+            // is still to come or the timeout catches it
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => bail!("nl80211 session closed"),
+        };
+
+        if !event.is_for_interface(iface) {
+            continue;
+        }
+
+        match event.cmd {
+            Nl80211Cmd::NewScanResults => return Ok(()),
+            Nl80211Cmd::ScanAborted => return Err(ScanError::Aborted.into()),
+            _ => {}
+        }
+    }
 }
 
-fn create_get_interface_message(nl_id: u16) -> Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>> {
-    let attrs = GenlBuffer::<Nl80211Attr, Buffer>::new();
-    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::GetInterface, 1, attrs);
-    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
-    let payload = NlPayload::Payload(genl_msghdr);
-    Nlmsghdr::new(None, nl_id, flags, None, None, payload)
+pub(super) async fn get_scan_results(
+    session: &Nl80211Session,
+    iface_index: u32,
+) -> Result<Vec<Bss>> {
+    let nl_msghdr = create_get_scan_message(session.nl_id(), iface_index);
+
+    session
+        .dump(nl_msghdr, |payload| Bss::try_from(&payload).ok())
+        .await
+        .context("Failed to receive get scan results response")
 }
 
 fn create_trigger_scan_message(
     nl_id: u16,
     iface_index: u32,
+    options: &ScanOptions,
+    frequencies: &[u32],
+    flags: u32,
 ) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
     let iface_attr = Nlattr::new(false, true, Nl80211Attr::Ifindex, iface_index)
         .context("Faled to create interface index attribute")?;
-    let scan_attr = Nlattr::new(false, true, Nl80211Attr::ScanFlags, NL80211_SCAN_FLAG_AP)
+    let scan_attr = Nlattr::new(false, true, Nl80211Attr::ScanFlags, flags)
         .context("Failed to create scan flags attribute")?;
-    let genl_msghdr = Genlmsghdr::new(
-        Nl80211Cmd::TriggerScan,
-        1,
-        [iface_attr, scan_attr].into_iter().collect(),
-    );
+
+    let mut attrs = vec![iface_attr, scan_attr];
+
+    // Without any SSID the kernel does not send probe requests
+    if !options.passive {
+        attrs.push(create_scan_ssids_attr(&options.ssids)?);
+    }
+
+    if !frequencies.is_empty() {
+        attrs.push(create_scan_frequencies_attr(frequencies)?);
+    }
+
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::TriggerScan, 1, attrs.into_iter().collect());
 
     let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Ack]);
     let payload = NlPayload::Payload(genl_msghdr);
     Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
 }
 
+/// Directed probes replace the broadcast probe, so the wildcard SSID is
+/// listed first to keep finding all other networks.
+fn create_scan_ssids_attr(ssids: &[String]) -> Result<Nlattr<Nl80211Attr, Buffer>> {
+    let mut scan_ssids_attr = Nlattr::new(true, false, Nl80211Attr::ScanSsids, Buffer::new())
+        .context("Failed to create scan SSIDs attribute")?;
+
+    let wildcard: &[u8] = &[];
+    let ssids = std::iter::once(wildcard).chain(ssids.iter().map(String::as_bytes));
+
+    for (index, ssid) in (1_u16..).zip(ssids) {
+        let ssid_attr =
+            Nlattr::new(false, false, index, ssid).context("Failed to create SSID attribute")?;
+        scan_ssids_attr
+            .add_nested_attribute(&ssid_attr)
+            .context("Failed to add SSID attribute")?;
+    }
+
+    Ok(scan_ssids_attr)
+}
+
+fn create_scan_frequencies_attr(frequencies: &[u32]) -> Result<Nlattr<Nl80211Attr, Buffer>> {
+    let mut scan_frequencies_attr =
+        Nlattr::new(true, false, Nl80211Attr::ScanFrequencies, Buffer::new())
+            .context("Failed to create scan frequencies attribute")?;
+
+    for (index, frequency) in (1_u16..).zip(frequencies) {
+        let frequency_attr = Nlattr::new(false, true, index, *frequency)
+            .context("Failed to create frequency attribute")?;
+        scan_frequencies_attr
+            .add_nested_attribute(&frequency_attr)
+            .context("Failed to add frequency attribute")?;
+    }
+
+    Ok(scan_frequencies_attr)
+}
+
 fn create_get_scan_message(
     nl_id: u16,
     iface_index: u32,
@@ -205,59 +352,3 @@ fn create_get_scan_message(
     let payload = NlPayload::Payload(genl_msghdr);
     Nlmsghdr::new(None, nl_id, flags, None, None, payload)
 }
-
-async fn recv_all<T, F>(socket: &mut NlSocket, mut f: F) -> Result<Vec<T>>
-where
-    F: FnMut(Nlmsghdr<Nlmsg, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>) -> Option<T>,
-{
-    let mut items = Vec::new();
-
-    'outer: loop {
-        let mut buf = vec![0; MAX_NL_LENGTH];
-
-        let msgs = socket
-            .recv::<Nlmsg, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>(&mut buf)
-            .await
-            .context("Failed to receive nl80211 command response")?;
-
-        for msg in msgs {
-            if msg.nl_type == Nlmsg::Done {
-                break 'outer;
-            }
-
-            if let Some(item) = f(msg) {
-                items.push(item);
-            }
-        }
-    }
-
-    Ok(items)
-}
-
-fn extract_ssid(cursor: &mut std::io::Cursor<&[u8]>) -> Vec<u8> {
-    while let Some((eid, data)) = extract_element(cursor) {
-        if eid == WLAN_EID_SSID {
-            return data;
-        }
-    }
-
-    Vec::new()
-}
-
-fn extract_element(cursor: &mut std::io::Cursor<&[u8]>) -> Option<(u8, Vec<u8>)> {
-    let eid = cursor.read_u8().ok()?;
-    let size = cursor.read_u8().ok()?;
-    let mut data = vec![0; size.into()];
-    cursor.read_exact(&mut data).ok()?;
-    Some((eid, data))
-}
-
-#[allow(clippy::as_conversions)]
-fn dbm_level_to_quality(signal: i32) -> u8 {
-    let mut val = f64::from(signal) / 100.;
-    val = val.clamp(-100., -40.);
-    val = (val + 40.).abs();
-    val = (100. - (100. * val) / 60.).round();
-    val = val.clamp(0., 100.);
-    val as u8
-}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use neli::consts::MAX_NL_LENGTH;
+use neli::genl::Genlmsghdr;
+use neli::nl::Nlmsghdr;
+use neli::socket::tokio::NlSocket;
+
+use tokio::io::AsyncReadExt;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd};
+use crate::nl80211::interface::Interface;
+use crate::nl80211::request::{
+    create_main_socket, create_multicast_socket, parse_genl_payload, parse_messages, NlMessage,
+    NlMessageKind,
+};
+
+const SCAN_EVENTS_CAPACITY: usize = 64;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Reported by features that need nl80211 when the kernel lacks it.
+pub const NL80211_NOT_AVAILABLE: &str = "nl80211 is not available";
+
+type Reply = Result<NlMessageKind>;
+
+struct Request {
+    nl_msghdr: Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>,
+    replies: mpsc::UnboundedSender<Reply>,
+}
+
+/// Notification from the `scan` multicast group.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ScanEvent {
+    pub cmd: Nl80211Cmd,
+    pub ifindex: Option<u32>,
+    pub wdev: Option<u64>,
+}
+
+impl ScanEvent {
+    /// Notifications carry the interface index, or only the wireless device
+    /// id for devices without a netdev.
+    pub fn is_for_interface(&self, iface: &Interface) -> bool {
+        match self.ifindex {
+            Some(ifindex) => ifindex == iface.index,
+            None => self.wdev == Some(iface.wdev),
+        }
+    }
+}
+
+/// A long lived nl80211 connection shared by all queries. The sockets are
+/// owned by a task on the tokio runtime, which sends the requests and routes
+/// the replies back by sequence number, so that queries can run
+/// concurrently. Cheap to clone.
+#[derive(Clone)]
+pub struct Nl80211Session {
+    nl_id: u16,
+    requests: mpsc::UnboundedSender<Request>,
+    scan_events: broadcast::Sender<ScanEvent>,
+}
+
+impl Nl80211Session {
+    /// Connects to nl80211. Has to be called from within the tokio runtime.
+    pub fn new() -> Result<Self> {
+        let sockets = Sockets::connect()?;
+        let nl_id = sockets.nl_id;
+
+        let (requests, requests_receiver) = mpsc::unbounded_channel();
+        let (scan_events, _) = broadcast::channel(SCAN_EVENTS_CAPACITY);
+
+        tokio::spawn(run_sessions(
+            sockets,
+            requests_receiver,
+            scan_events.clone(),
+        ));
+
+        Ok(Self {
+            nl_id,
+            requests,
+            scan_events,
+        })
+    }
+
+    /// Generic netlink family id of nl80211, the type of all requests.
+    pub(super) fn nl_id(&self) -> u16 {
+        self.nl_id
+    }
+
+    /// Subscribe before triggering a scan, so that a scan completing quickly
+    /// is not missed.
+    pub(super) fn subscribe_scan_events(&self) -> broadcast::Receiver<ScanEvent> {
+        self.scan_events.subscribe()
+    }
+
+    /// Sends a dump request and collects the replies. Errors are returned as
+    /// `Nl80211Error`, the kernel does not finish the dump after them.
+    pub(super) async fn dump<T, F>(
+        &self,
+        nl_msghdr: Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>,
+        mut f: F,
+    ) -> Result<Vec<T>>
+    where
+        F: FnMut(Genlmsghdr<Nl80211Cmd, Nl80211Attr>) -> Option<T>,
+    {
+        let mut replies = self.send(nl_msghdr)?;
+        let mut items = Vec::new();
+
+        while let Some(reply) = replies.recv().await {
+            match reply? {
+                NlMessageKind::Payload(payload) => {
+                    if let Some(item) = parse_genl_payload(&payload).ok().and_then(&mut f) {
+                        items.push(item);
+                    }
+                }
+                NlMessageKind::Done => return Ok(items),
+                NlMessageKind::Err(err) => return Err(err.into()),
+                NlMessageKind::Ack => {}
+            }
+        }
+
+        bail!("nl80211 session closed")
+    }
+
+    /// Sends a request flagged with `NlmF::Ack` and waits for the kernel to
+    /// acknowledge it, skipping any reply that precedes the acknowledgement.
+    /// Errors are returned as `Nl80211Error`.
+    pub(super) async fn ack(
+        &self,
+        nl_msghdr: Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>,
+    ) -> Result<()> {
+        let mut replies = self.send(nl_msghdr)?;
+
+        while let Some(reply) = replies.recv().await {
+            match reply? {
+                NlMessageKind::Ack => return Ok(()),
+                NlMessageKind::Err(err) => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        bail!("nl80211 session closed")
+    }
+
+    fn send(
+        &self,
+        nl_msghdr: Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>,
+    ) -> Result<mpsc::UnboundedReceiver<Reply>> {
+        let (replies, replies_receiver) = mpsc::unbounded_channel();
+
+        self.requests
+            .send(Request { nl_msghdr, replies })
+            .map_err(|_| anyhow!("nl80211 session closed"))?;
+
+        Ok(replies_receiver)
+    }
+}
+
+struct Sockets {
+    socket: NlSocket,
+    nl_id: u16,
+    socket_mcast: NlSocket,
+}
+
+impl Sockets {
+    fn connect() -> Result<Self> {
+        let (socket, nl_id) = create_main_socket()?;
+        let socket_mcast = create_multicast_socket()?;
+
+        Ok(Self {
+            socket,
+            nl_id,
+            socket_mcast,
+        })
+    }
+}
+
+enum Event {
+    Request(Option<Request>),
+    Replies(io::Result<usize>),
+    Notifications(io::Result<usize>),
+}
+
+/// Reconnects whenever the sockets fail. Requests in flight fail with the
+/// session, later ones are sent on the new sockets.
+async fn run_sessions(
+    mut sockets: Sockets,
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    scan_events: broadcast::Sender<ScanEvent>,
+) {
+    loop {
+        match run_session(sockets, &mut requests, &scan_events).await {
+            // All sessions are gone
+            Ok(()) => return,
+            Err(err) => println!("nl80211 session failed, reconnecting: {:#}", err),
+        }
+
+        sockets = loop {
+            tokio::time::sleep(RECONNECT_DELAY).await;
+
+            match Sockets::connect() {
+                Ok(sockets) => break sockets,
+                Err(err) => println!("Failed to reconnect to nl80211: {:#}", err),
+            }
+        };
+    }
+}
+
+async fn run_session(
+    sockets: Sockets,
+    requests: &mut mpsc::UnboundedReceiver<Request>,
+    scan_events: &broadcast::Sender<ScanEvent>,
+) -> Result<()> {
+    let Sockets {
+        mut socket,
+        nl_id,
+        mut socket_mcast,
+    } = sockets;
+
+    let mut pending: HashMap<u32, mpsc::UnboundedSender<Reply>> = HashMap::new();
+    let mut next_seq: u32 = 1;
+
+    let mut buf = vec![0; MAX_NL_LENGTH];
+    let mut buf_mcast = vec![0; MAX_NL_LENGTH];
+
+    loop {
+        let event = tokio::select! {
+            request = requests.recv() => Event::Request(request),
+            len = socket.read(&mut buf) => Event::Replies(len),
+            len = socket_mcast.read(&mut buf_mcast) => Event::Notifications(len),
+        };
+
+        match event {
+            Event::Request(Some(Request {
+                mut nl_msghdr,
+                replies,
+            })) => {
+                let seq = next_seq;
+                next_seq = next_seq.checked_add(1).unwrap_or(1);
+
+                // The family id may have changed since the request was
+                // created, if nl80211 was reloaded before reconnecting
+                nl_msghdr.nl_type = nl_id;
+                nl_msghdr.nl_seq = seq;
+
+                match socket.send(&nl_msghdr).await {
+                    Ok(()) => {
+                        pending.insert(seq, replies);
+                    }
+                    Err(err) => {
+                        let err = anyhow::Error::new(err).context("Failed to send nl80211 request");
+                        replies.send(Err(err)).ok();
+                    }
+                }
+            }
+            Event::Request(None) => return Ok(()),
+            Event::Replies(Ok(len)) => match parse_messages(&buf[..len]) {
+                Ok(msgs) => {
+                    for msg in msgs {
+                        route_reply(&mut pending, msg);
+                    }
+                }
+                Err(err) => {
+                    let message = format!("Failed to parse nl80211 response: {:#}", err);
+                    fail_pending(&mut pending, &message);
+                }
+            },
+            Event::Replies(Err(err)) => {
+                // Replies may have been lost, so none of the pending requests
+                // can complete
+                let message = format!("Failed to receive nl80211 response: {}", err);
+                fail_pending(&mut pending, &message);
+
+                if !is_overrun(&err) {
+                    return Err(err).context("Failed to receive nl80211 response");
+                }
+            }
+            Event::Notifications(Ok(len)) => match parse_messages(&buf_mcast[..len]) {
+                Ok(msgs) => {
+                    for msg in msgs {
+                        if let Some(event) = parse_scan_event(&msg) {
+                            scan_events.send(event).ok();
+                        }
+                    }
+                }
+                Err(err) => println!("Failed to parse nl80211 notification: {:#}", err),
+            },
+            Event::Notifications(Err(err)) => {
+                println!("Failed to receive nl80211 notification: {}", err);
+
+                if !is_overrun(&err) {
+                    return Err(err).context("Failed to receive nl80211 notification");
+                }
+            }
+        }
+    }
+}
+
+/// The socket buffer overflowed and messages were dropped, the socket
+/// itself is still usable.
+fn is_overrun(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+fn fail_pending(pending: &mut HashMap<u32, mpsc::UnboundedSender<Reply>>, message: &str) {
+    for (_, replies) in pending.drain() {
+        replies.send(Err(anyhow!(message.to_owned()))).ok();
+    }
+}
+
+/// Dumps end with `Done`, other requests with an acknowledgement or error.
+fn route_reply(pending: &mut HashMap<u32, mpsc::UnboundedSender<Reply>>, msg: NlMessage) {
+    let NlMessage { seq, kind } = msg;
+    let is_last = !matches!(kind, NlMessageKind::Payload(_));
+
+    if let Some(replies) = pending.get(&seq) {
+        replies.send(Ok(kind)).ok();
+    }
+
+    if is_last {
+        pending.remove(&seq);
+    }
+}
+
+fn parse_scan_event(msg: &NlMessage) -> Option<ScanEvent> {
+    let payload = match msg.kind {
+        NlMessageKind::Payload(ref payload) => parse_genl_payload(payload).ok()?,
+        _ => return None,
+    };
+
+    let attrs = payload.get_attr_handle();
+
+    Some(ScanEvent {
+        cmd: payload.cmd,
+        ifindex: attrs.get_attr_payload_as(Nl80211Attr::Ifindex).ok(),
+        wdev: attrs.get_attr_payload_as(Nl80211Attr::Wdev).ok(),
+    })
+}
@@ -0,0 +1,252 @@
+use std::convert::{TryFrom, TryInto};
+
+use anyhow::{Context, Result};
+
+use macaddr::MacAddr6;
+
+use neli::consts::nl::{NlmF, NlmFFlags};
+use neli::genl::{Genlmsghdr, Nlattr};
+use neli::nl::{NlPayload, Nlmsghdr};
+use neli::types::Buffer;
+
+use serde::Serialize;
+
+use crate::nl80211::bss::serialize_mac;
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd, Nl80211RateInfo, Nl80211StaInfo};
+use crate::nl80211::interface::find_interface;
+use crate::nl80211::session::Nl80211Session;
+
+/// Management frame subtype of deauthentication frames
+const MGMT_SUBTYPE_DEAUTH: u8 = 12;
+
+/// Reason code "previous authentication no longer valid", also sent by
+/// hostapd when a client is disconnected on purpose
+const REASON_PREV_AUTH_NOT_VALID: u16 = 2;
+
+/// A client associated with an access point interface.
+#[derive(Serialize, Debug, Clone)]
+pub struct StationInfo {
+    #[serde(serialize_with = "serialize_mac")]
+    pub mac: MacAddr6,
+    pub connected_secs: Option<u32>,
+    pub inactive_ms: Option<u32>,
+    pub signal_dbm: Option<i8>,
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+    pub rx_rate: Option<RateInfo>,
+    pub tx_rate: Option<RateInfo>,
+}
+
+/// Rate of the last frame sent or received.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct RateInfo {
+    pub bitrate_kbps: Option<u32>,
+    /// HT, VHT or HE modulation and coding scheme
+    pub mcs: Option<u8>,
+    /// Number of spatial streams
+    pub nss: Option<u8>,
+}
+
+impl TryFrom<&Nlattr<Nl80211StaInfo, Buffer>> for RateInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(rate_attr: &Nlattr<Nl80211StaInfo, Buffer>) -> Result<Self, Self::Error> {
+        let attrs = rate_attr.get_attr_handle::<Nl80211RateInfo>()?;
+
+        // Bitrates are reported in units of 100 kbit/s. The 16 bit attribute
+        // is only present for rates that fit into it.
+        let bitrate_kbps = attrs
+            .get_attr_payload_as::<u32>(Nl80211RateInfo::Bitrate32)
+            .ok()
+            .or_else(|| {
+                attrs
+                    .get_attr_payload_as::<u16>(Nl80211RateInfo::Bitrate)
+                    .ok()
+                    .map(u32::from)
+            })
+            .map(|bitrate| bitrate * 100);
+
+        let he = (
+            attrs.get_attr_payload_as(Nl80211RateInfo::HeMcs).ok(),
+            attrs.get_attr_payload_as(Nl80211RateInfo::HeNss).ok(),
+        );
+        let vht = (
+            attrs.get_attr_payload_as(Nl80211RateInfo::VhtMcs).ok(),
+            attrs.get_attr_payload_as(Nl80211RateInfo::VhtNss).ok(),
+        );
+        // HT MCS indices 0-31 count 8 per spatial stream. Index 32 and the
+        // unequal modulation indices above it are reported as they are.
+        let ht = attrs
+            .get_attr_payload_as::<u8>(Nl80211RateInfo::Mcs)
+            .ok()
+            .map(|mcs| match mcs {
+                0..=31 => (Some(mcs % 8), Some(mcs / 8 + 1)),
+                _ => (Some(mcs), None),
+            });
+
+        let (mcs, nss) = match (he, vht, ht) {
+            ((Some(mcs), nss), _, _) | (_, (Some(mcs), nss), _) => (Some(mcs), nss),
+            (_, _, Some(ht)) => ht,
+            _ => (None, None),
+        };
+
+        Ok(Self {
+            bitrate_kbps,
+            mcs,
+            nss,
+        })
+    }
+}
+
+impl TryFrom<&Genlmsghdr<Nl80211Cmd, Nl80211Attr>> for StationInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(payload: &Genlmsghdr<Nl80211Cmd, Nl80211Attr>) -> Result<Self, Self::Error> {
+        let mut attrs = payload.get_attr_handle();
+
+        let mac_bytes: [u8; 6] = attrs
+            .get_attr_payload_as_with_len::<&[u8]>(Nl80211Attr::Mac)?
+            .try_into()?;
+
+        let mut station = Self {
+            mac: mac_bytes.into(),
+            connected_secs: None,
+            inactive_ms: None,
+            signal_dbm: None,
+            rx_bytes: None,
+            tx_bytes: None,
+            rx_rate: None,
+            tx_rate: None,
+        };
+
+        let sta_info = match attrs.get_nested_attributes::<Nl80211StaInfo>(Nl80211Attr::StaInfo) {
+            Ok(sta_info) => sta_info,
+            Err(_) => return Ok(station),
+        };
+
+        station.connected_secs = sta_info
+            .get_attr_payload_as(Nl80211StaInfo::ConnectedTime)
+            .ok();
+        station.inactive_ms = sta_info
+            .get_attr_payload_as(Nl80211StaInfo::InactiveTime)
+            .ok();
+        station.signal_dbm = sta_info
+            .get_attr_payload_as::<u8>(Nl80211StaInfo::Signal)
+            .ok()
+            .map(|signal| i8::from_ne_bytes([signal]));
+
+        // The 32 bit counters wrap after 4 GiB, prefer the 64 bit ones
+        station.rx_bytes = sta_info
+            .get_attr_payload_as(Nl80211StaInfo::RxBytes64)
+            .ok()
+            .or_else(|| {
+                sta_info
+                    .get_attr_payload_as::<u32>(Nl80211StaInfo::RxBytes)
+                    .ok()
+                    .map(u64::from)
+            });
+        station.tx_bytes = sta_info
+            .get_attr_payload_as(Nl80211StaInfo::TxBytes64)
+            .ok()
+            .or_else(|| {
+                sta_info
+                    .get_attr_payload_as::<u32>(Nl80211StaInfo::TxBytes)
+                    .ok()
+                    .map(u64::from)
+            });
+
+        station.rx_rate = sta_info
+            .get_attribute(Nl80211StaInfo::RxBitrate)
+            .and_then(|attr| RateInfo::try_from(attr).ok());
+        station.tx_rate = sta_info
+            .get_attribute(Nl80211StaInfo::TxBitrate)
+            .and_then(|attr| RateInfo::try_from(attr).ok());
+
+        Ok(station)
+    }
+}
+
+/// Returns the number of clients associated with an access point interface.
+pub async fn count_stations(session: &Nl80211Session, interface: &str) -> Result<usize> {
+    Ok(get_station_infos(session, interface).await?.len())
+}
+
+/// Returns the clients associated with an access point interface.
+pub async fn get_station_infos(
+    session: &Nl80211Session,
+    interface: &str,
+) -> Result<Vec<StationInfo>> {
+    let iface = find_interface(session, interface).await?;
+
+    get_stations(session, iface.index)
+        .await
+        .context("Failed to get stations")
+}
+
+/// Deauthenticates a client from an access point interface.
+pub async fn disconnect_station(
+    session: &Nl80211Session,
+    interface: &str,
+    mac: MacAddr6,
+) -> Result<()> {
+    let iface = find_interface(session, interface).await?;
+
+    let nl_msghdr = create_del_station_message(session.nl_id(), iface.index, mac)?;
+
+    session
+        .ack(nl_msghdr)
+        .await
+        .context("Failed to delete station")
+}
+
+pub(super) async fn get_stations(
+    session: &Nl80211Session,
+    iface_index: u32,
+) -> Result<Vec<StationInfo>> {
+    let nl_msghdr = create_get_station_message(session.nl_id(), iface_index)?;
+
+    session
+        .dump(nl_msghdr, |payload| StationInfo::try_from(&payload).ok())
+        .await
+        .context("Failed to receive get station response")
+}
+
+fn create_get_station_message(
+    nl_id: u16,
+    iface_index: u32,
+) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
+    let attr = Nlattr::new(false, true, Nl80211Attr::Ifindex, iface_index)
+        .context("Failed to create interface index attribute")?;
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::GetStation, 1, [attr].into_iter().collect());
+
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
+}
+
+fn create_del_station_message(
+    nl_id: u16,
+    iface_index: u32,
+    mac: MacAddr6,
+) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
+    let iface_attr = Nlattr::new(false, true, Nl80211Attr::Ifindex, iface_index)
+        .context("Failed to create interface index attribute")?;
+    let mac_attr = Nlattr::new(false, false, Nl80211Attr::Mac, mac.as_bytes())
+        .context("Failed to create MAC address attribute")?;
+    let subtype_attr = Nlattr::new(false, true, Nl80211Attr::MgmtSubtype, MGMT_SUBTYPE_DEAUTH)
+        .context("Failed to create management frame subtype attribute")?;
+    let reason_attr = Nlattr::new(
+        false,
+        true,
+        Nl80211Attr::ReasonCode,
+        REASON_PREV_AUTH_NOT_VALID,
+    )
+    .context("Failed to create reason code attribute")?;
+
+    let attrs = [iface_attr, mac_attr, subtype_attr, reason_attr];
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::DelStation, 1, attrs.into_iter().collect());
+
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Ack]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
+}
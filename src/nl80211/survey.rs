@@ -0,0 +1,306 @@
+use std::convert::TryFrom;
+
+use anyhow::{Context, Result};
+
+use neli::consts::nl::{NlmF, NlmFFlags};
+use neli::genl::{Genlmsghdr, Nlattr};
+use neli::nl::{NlPayload, Nlmsghdr};
+
+use serde::Serialize;
+
+use crate::nl80211::bss::{frequency_to_band, frequency_to_channel, Band, Bss};
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd, Nl80211SurveyInfo};
+use crate::nl80211::interface::find_interface;
+use crate::nl80211::scan::get_scan_results;
+use crate::nl80211::session::Nl80211Session;
+use crate::nl80211::wiphy::dump_wiphy;
+
+/// Access points closer than this in MHz share spectrum with a 20 MHz channel
+const OVERLAP_MHZ: u32 = 25;
+
+/// Penalty of an access point on the same channel, as much as a channel
+/// busy 10% of the time
+const BSS_PENALTY: u32 = 10;
+
+/// Noise below this level does not count against a channel
+const NOISE_FLOOR_DBM: i32 = -95;
+
+/// Radio activity measured on a channel. Which values are reported depends
+/// on the driver.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChannelSurvey {
+    /// Center frequency in MHz
+    pub frequency: u32,
+    pub channel: Option<u32>,
+    pub band: Option<Band>,
+    /// The radio is currently tuned to this channel
+    pub in_use: bool,
+    pub noise_dbm: Option<i8>,
+    /// Time spent on the channel
+    pub active_ms: Option<u64>,
+    /// Time the medium was sensed busy, including our own transmissions
+    pub busy_ms: Option<u64>,
+    pub rx_ms: Option<u64>,
+    pub tx_ms: Option<u64>,
+}
+
+impl ChannelSurvey {
+    /// Share of the active time the channel was used by others, in percent.
+    pub fn utilization(&self) -> Option<u32> {
+        let active = self.active_ms.filter(|active| *active > 0)?;
+        let busy = self.busy_ms?.saturating_sub(self.tx_ms.unwrap_or_default());
+
+        u32::try_from(busy.min(active) * 100 / active).ok()
+    }
+}
+
+impl TryFrom<&Genlmsghdr<Nl80211Cmd, Nl80211Attr>> for ChannelSurvey {
+    type Error = anyhow::Error;
+
+    fn try_from(payload: &Genlmsghdr<Nl80211Cmd, Nl80211Attr>) -> Result<Self, Self::Error> {
+        let mut attrs = payload.get_attr_handle();
+
+        let survey_info =
+            attrs.get_nested_attributes::<Nl80211SurveyInfo>(Nl80211Attr::SurveyInfo)?;
+
+        let frequency = survey_info.get_attr_payload_as(Nl80211SurveyInfo::Frequency)?;
+
+        Ok(Self {
+            frequency,
+            channel: frequency_to_channel(frequency),
+            band: frequency_to_band(frequency),
+            in_use: survey_info
+                .get_attribute(Nl80211SurveyInfo::InUse)
+                .is_some(),
+            noise_dbm: survey_info
+                .get_attr_payload_as::<u8>(Nl80211SurveyInfo::Noise)
+                .ok()
+                .map(|noise| i8::from_ne_bytes([noise])),
+            active_ms: survey_info
+                .get_attr_payload_as(Nl80211SurveyInfo::Time)
+                .ok(),
+            busy_ms: survey_info
+                .get_attr_payload_as(Nl80211SurveyInfo::TimeBusy)
+                .ok(),
+            rx_ms: survey_info
+                .get_attr_payload_as(Nl80211SurveyInfo::TimeRx)
+                .ok(),
+            tx_ms: survey_info
+                .get_attr_payload_as(Nl80211SurveyInfo::TimeTx)
+                .ok(),
+        })
+    }
+}
+
+/// How crowded a channel is, the lower the score the better.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ChannelScore {
+    /// Center frequency in MHz
+    pub frequency: u32,
+    pub channel: u32,
+    /// Access points on this or an overlapping channel
+    pub bss_count: usize,
+    /// Busy time in percent, when the driver reports it
+    pub utilization: Option<u32>,
+    pub noise_dbm: Option<i8>,
+    pub score: u32,
+}
+
+impl ChannelScore {
+    /// Access points count fully on the same channel and less the further
+    /// away they are on an overlapping one. Wider channels of access points
+    /// are not accounted for.
+    fn new(frequency: u32, channel: u32, survey: Option<&ChannelSurvey>, bsss: &[Bss]) -> Self {
+        let overlapping: Vec<_> = bsss
+            .iter()
+            .map(|bss| bss.frequency.abs_diff(frequency))
+            .filter(|distance| *distance < OVERLAP_MHZ)
+            .collect();
+
+        let bss_penalty: u32 = overlapping
+            .iter()
+            .map(|distance| (OVERLAP_MHZ - distance) * BSS_PENALTY / OVERLAP_MHZ)
+            .sum();
+
+        let utilization = survey.and_then(ChannelSurvey::utilization);
+        let noise_dbm = survey.and_then(|survey| survey.noise_dbm);
+
+        let noise_penalty = noise_dbm
+            .map(|noise| i32::from(noise) - NOISE_FLOOR_DBM)
+            .and_then(|excess| u32::try_from(excess).ok())
+            .unwrap_or_default();
+
+        Self {
+            frequency,
+            channel,
+            bss_count: overlapping.len(),
+            utilization,
+            noise_dbm,
+            score: bss_penalty + utilization.unwrap_or_default() + noise_penalty,
+        }
+    }
+}
+
+/// Scores the channels of `band` an access point may be started on, best
+/// first. Combines the channel survey with the access points found by the
+/// last scan, without scanning again. Drivers without survey support are
+/// scored by the access points only.
+pub async fn score_channels(
+    session: &Nl80211Session,
+    interface: &str,
+    band: Band,
+) -> Result<Vec<ChannelScore>> {
+    let iface = find_interface(session, interface).await?;
+
+    let wiphy = dump_wiphy(session, iface.wiphy)
+        .await
+        .context("Failed to get wiphy")?;
+
+    let surveys = match dump_survey(session, iface.index).await {
+        Ok(surveys) => surveys,
+        Err(err) => {
+            println!("Channel survey not available on {}: {:#}", interface, err);
+            Vec::new()
+        }
+    };
+
+    let bsss = get_scan_results(session, iface.index)
+        .await
+        .context("Failed to get scan results")?;
+
+    let mut scores: Vec<_> = wiphy
+        .ap_frequencies(band)
+        .into_iter()
+        .filter_map(|frequency| {
+            let survey = surveys
+                .iter()
+                .find(|survey| survey.frequency == frequency.frequency);
+
+            frequency
+                .channel
+                .map(|channel| ChannelScore::new(frequency.frequency, channel, survey, &bsss))
+        })
+        .collect();
+
+    scores.sort_by_key(|score| (score.score, score.frequency));
+
+    Ok(scores)
+}
+
+async fn dump_survey(session: &Nl80211Session, iface_index: u32) -> Result<Vec<ChannelSurvey>> {
+    let nl_msghdr = create_get_survey_message(session.nl_id(), iface_index)?;
+
+    session
+        .dump(nl_msghdr, |payload| ChannelSurvey::try_from(&payload).ok())
+        .await
+        .context("Failed to receive get survey response")
+}
+
+fn create_get_survey_message(
+    nl_id: u16,
+    iface_index: u32,
+) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
+    let attr = Nlattr::new(false, true, Nl80211Attr::Ifindex, iface_index)
+        .context("Failed to create interface index attribute")?;
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::GetSurvey, 1, [attr].into_iter().collect());
+
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use macaddr::MacAddr6;
+
+    use super::*;
+    use crate::network::Security;
+
+    fn survey(active_ms: Option<u64>, busy_ms: Option<u64>, tx_ms: Option<u64>) -> ChannelSurvey {
+        ChannelSurvey {
+            frequency: 2437,
+            channel: Some(6),
+            band: Some(Band::Band2GHz),
+            in_use: false,
+            noise_dbm: None,
+            active_ms,
+            busy_ms,
+            rx_ms: None,
+            tx_ms,
+        }
+    }
+
+    fn bss(frequency: u32) -> Bss {
+        Bss {
+            bssid: MacAddr6::nil(),
+            ssid: "Neighbor".to_owned(),
+            hidden: false,
+            connectable: true,
+            frequency,
+            channel: frequency_to_channel(frequency),
+            band: frequency_to_band(frequency),
+            signal_dbm: -60,
+            seen_ms_ago: 0,
+            chan_width_mhz: 20,
+            security: Security::Wpa2,
+            associated: false,
+        }
+    }
+
+    #[test]
+    fn utilization_excludes_own_transmissions() {
+        assert_eq!(
+            survey(Some(1000), Some(600), Some(100)).utilization(),
+            Some(50)
+        );
+        assert_eq!(survey(Some(1000), Some(600), None).utilization(), Some(60));
+        assert_eq!(
+            survey(Some(1000), Some(100), Some(600)).utilization(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn utilization_is_capped_at_active_time() {
+        assert_eq!(
+            survey(Some(1000), Some(1500), None).utilization(),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn utilization_needs_active_and_busy_time() {
+        assert_eq!(survey(None, Some(600), None).utilization(), None);
+        assert_eq!(survey(Some(0), Some(600), None).utilization(), None);
+        assert_eq!(survey(Some(1000), None, Some(100)).utilization(), None);
+    }
+
+    #[test]
+    fn score_counts_overlapping_access_points() {
+        // Same channel, one channel away and five channels away
+        let bsss = [bss(2437), bss(2442), bss(2462)];
+
+        let score = ChannelScore::new(2437, 6, None, &bsss);
+
+        assert_eq!(score.bss_count, 2);
+        assert_eq!(score.utilization, None);
+        assert_eq!(score.score, BSS_PENALTY + 8);
+    }
+
+    #[test]
+    fn score_adds_utilization_and_noise() {
+        let mut survey = survey(Some(1000), Some(300), None);
+        survey.noise_dbm = Some(-90);
+
+        let score = ChannelScore::new(2437, 6, Some(&survey), &[bss(2437)]);
+        assert_eq!(score.utilization, Some(30));
+        assert_eq!(score.score, BSS_PENALTY + 30 + 5);
+
+        // Noise below the floor is not penalized
+        survey.noise_dbm = Some(-100);
+
+        let score = ChannelScore::new(2437, 6, Some(&survey), &[]);
+        assert_eq!(score.bss_count, 0);
+        assert_eq!(score.score, 30);
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{bail, Context, Result};
+
+use neli::consts::nl::{NlmF, NlmFFlags};
+use neli::genl::{Genlmsghdr, Nlattr};
+use neli::nl::{NlPayload, Nlmsghdr};
+
+use crate::nl80211::consts::NL80211_IFTYPE_AP;
+use crate::nl80211::enums::{Nl80211Attr, Nl80211Cmd};
+use crate::nl80211::interface::{get_interfaces, Interface, InterfaceType};
+use crate::nl80211::session::Nl80211Session;
+
+/// Creates an access point interface named `name` on the same radio as
+/// `parent`. An interface left over from an earlier run is reused.
+pub async fn create_ap_interface(session: &Nl80211Session, parent: &str, name: &str) -> Result<()> {
+    let ifaces = get_interfaces(session)
+        .await
+        .context("Failed to get interfaces")?;
+
+    let parent_iface = ifaces
+        .iter()
+        .find(|iface| iface.name == parent)
+        .context(format!("Interface '{}' not found", parent))?;
+
+    if let Some(iface) = ifaces.iter().find(|iface| iface.name == name) {
+        return check_virtual_ap_interface(iface, parent_iface);
+    }
+
+    let nl_msghdr = create_new_interface_message(session.nl_id(), parent_iface.wiphy, name)?;
+
+    session
+        .ack(nl_msghdr)
+        .await
+        .context("Failed to create interface")
+}
+
+/// Removes a virtual access point interface of the radio of `parent`. Does
+/// nothing when it is already gone.
+pub async fn delete_ap_interface(session: &Nl80211Session, parent: &str, name: &str) -> Result<()> {
+    let ifaces = get_interfaces(session)
+        .await
+        .context("Failed to get interfaces")?;
+
+    let iface = match ifaces.iter().find(|iface| iface.name == name) {
+        Some(iface) => iface,
+        None => return Ok(()),
+    };
+
+    let parent_iface = ifaces
+        .iter()
+        .find(|iface| iface.name == parent)
+        .context(format!("Interface '{}' not found", parent))?;
+
+    check_virtual_ap_interface(iface, parent_iface)?;
+
+    let nl_msghdr = create_del_interface_message(session.nl_id(), iface.index)?;
+
+    session
+        .ack(nl_msghdr)
+        .await
+        .context("Failed to delete interface")
+}
+
+/// Only interfaces `create_ap_interface` may have created are reused or
+/// deleted. NetworkManager switches them between access point and station
+/// mode, so both are accepted.
+fn check_virtual_ap_interface(iface: &Interface, parent: &Interface) -> Result<()> {
+    if iface.index == parent.index {
+        bail!(
+            "Interface '{}' is the station interface, not a virtual one",
+            iface.name
+        );
+    }
+
+    if iface.wiphy != parent.wiphy {
+        bail!(
+            "Interface '{}' is on another radio than '{}'",
+            iface.name,
+            parent.name
+        );
+    }
+
+    if !matches!(iface.iftype, InterfaceType::AP | InterfaceType::Station) {
+        bail!(
+            "Interface '{}' is a {:?} interface, not an access point",
+            iface.name,
+            iface.iftype
+        );
+    }
+
+    Ok(())
+}
+
+fn create_new_interface_message(
+    nl_id: u16,
+    wiphy: u32,
+    name: &str,
+) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
+    let wiphy_attr = Nlattr::new(false, true, Nl80211Attr::Wiphy, wiphy)
+        .context("Failed to create wiphy attribute")?;
+    // Interface names are passed as NUL terminated strings
+    let name_attr = Nlattr::new(false, false, Nl80211Attr::Ifname, name)
+        .context("Failed to create interface name attribute")?;
+    let iftype_attr = Nlattr::new(false, true, Nl80211Attr::Iftype, NL80211_IFTYPE_AP)
+        .context("Failed to create interface type attribute")?;
+
+    let attrs = [wiphy_attr, name_attr, iftype_attr];
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::NewInterface, 1, attrs.into_iter().collect());
+
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Ack]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
+}
+
+fn create_del_interface_message(
+    nl_id: u16,
+    iface_index: u32,
+) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
+    let attr = Nlattr::new(false, true, Nl80211Attr::Ifindex, iface_index)
+        .context("Failed to create interface index attribute")?;
+    let genl_msghdr = Genlmsghdr::new(Nl80211Cmd::DelInterface, 1, [attr].into_iter().collect());
+
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Ack]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use macaddr::MacAddr6;
+
+    use super::*;
+
+    fn iface(name: &str, index: u32, iftype: InterfaceType, wiphy: u32) -> Interface {
+        Interface {
+            name: name.to_owned(),
+            index,
+            iftype,
+            wiphy,
+            wdev: u64::from(index),
+            mac_address: MacAddr6::nil(),
+        }
+    }
+
+    #[test]
+    fn virtual_ap_interface_on_same_radio() {
+        let parent = iface("wlan0", 3, InterfaceType::Station, 0);
+
+        let ap = iface("uap0", 4, InterfaceType::AP, 0);
+        assert!(check_virtual_ap_interface(&ap, &parent).is_ok());
+
+        // Left in station mode by NetworkManager
+        let ap = iface("uap0", 4, InterfaceType::Station, 0);
+        assert!(check_virtual_ap_interface(&ap, &parent).is_ok());
+    }
+
+    #[test]
+    fn unrelated_interfaces_are_not_virtual_ap_interfaces() {
+        let parent = iface("wlan0", 3, InterfaceType::Station, 0);
+
+        assert!(check_virtual_ap_interface(&parent, &parent).is_err());
+
+        let other_radio = iface("wlan1", 5, InterfaceType::Station, 1);
+        assert!(check_virtual_ap_interface(&other_radio, &parent).is_err());
+
+        let monitor = iface("mon0", 6, InterfaceType::Monitor, 0);
+        assert!(check_virtual_ap_interface(&monitor, &parent).is_err());
+    }
+}
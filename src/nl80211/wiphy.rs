@@ -0,0 +1,435 @@
+use anyhow::{bail, Context, Result};
+
+use neli::attr::Attribute;
+use neli::consts::nl::{NlmF, NlmFFlags};
+use neli::genl::{Genlmsghdr, Nlattr};
+use neli::nl::{NlPayload, Nlmsghdr};
+use neli::types::Buffer;
+
+use serde::Serialize;
+
+use crate::nl80211::bss::{frequency_to_channel, Band};
+use crate::nl80211::consts;
+use crate::nl80211::enums::{
+    Nl80211Attr, Nl80211BandAttr, Nl80211Cmd, Nl80211FrequencyAttr, Nl80211IfaceCombAttr,
+    Nl80211IfaceLimitAttr,
+};
+use crate::nl80211::interface::{find_interface, InterfaceType};
+use crate::nl80211::session::Nl80211Session;
+
+/// Capabilities of the radio behind an interface.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Wiphy {
+    pub index: u32,
+    pub name: String,
+    pub iftypes: Vec<InterfaceType>,
+    pub bands: Vec<WiphyBand>,
+    pub cipher_suites: Vec<String>,
+    pub max_scan_ssids: u8,
+    pub combinations: Vec<InterfaceCombination>,
+    /// Probe requests can be sent from a random MAC address
+    pub scan_random_mac: bool,
+    pub scan_low_priority: bool,
+    pub scan_low_span: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WiphyBand {
+    pub band: Option<Band>,
+    pub frequencies: Vec<WiphyFrequency>,
+    #[serde(skip)]
+    index: u16,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WiphyFrequency {
+    /// Center frequency in MHz
+    pub frequency: u32,
+    pub channel: Option<u32>,
+    pub disabled: bool,
+    /// Initiating radiation, e.g. running an access point, is not allowed
+    pub no_ir: bool,
+    pub radar: bool,
+    pub max_tx_power_dbm: Option<u32>,
+}
+
+impl WiphyFrequency {
+    /// Whether an access point may be started on this frequency without
+    /// radar detection.
+    pub fn is_usable_for_ap(&self) -> bool {
+        !self.disabled && !self.no_ir && !self.radar
+    }
+}
+
+/// A set of interfaces that can be used at the same time.
+#[derive(Serialize, Debug, Clone)]
+pub struct InterfaceCombination {
+    pub limits: Vec<InterfaceLimit>,
+    pub max_interfaces: u32,
+    pub num_channels: u32,
+    pub sta_ap_beacon_int_match: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct InterfaceLimit {
+    pub max: u32,
+    pub iftypes: Vec<InterfaceType>,
+}
+
+impl Wiphy {
+    pub fn supports_ap(&self) -> bool {
+        self.iftypes.contains(&InterfaceType::AP)
+    }
+
+    /// Whether an access point can run next to a station interface, e.g. a
+    /// virtual portal interface while staying connected.
+    pub fn supports_ap_and_station(&self) -> bool {
+        self.combinations
+            .iter()
+            .any(InterfaceCombination::allows_ap_and_station)
+    }
+
+    /// Frequencies of `band` that are not disabled by regulatory rules.
+    pub fn band_frequencies(&self, band: Band) -> Vec<u32> {
+        self.bands
+            .iter()
+            .filter(|wiphy_band| wiphy_band.band == Some(band))
+            .flat_map(|wiphy_band| &wiphy_band.frequencies)
+            .filter(|frequency| !frequency.disabled)
+            .map(|frequency| frequency.frequency)
+            .collect()
+    }
+
+    /// Frequencies of a band an access point may be started on.
+    pub fn ap_frequencies(&self, band: Band) -> Vec<&WiphyFrequency> {
+        self.bands
+            .iter()
+            .filter(|wiphy_band| wiphy_band.band == Some(band))
+            .flat_map(|wiphy_band| &wiphy_band.frequencies)
+            .filter(|frequency| frequency.is_usable_for_ap())
+            .collect()
+    }
+
+    pub fn supports_band(&self, band: Band) -> bool {
+        self.bands.iter().any(|wiphy_band| {
+            wiphy_band.band == Some(band)
+                && wiphy_band
+                    .frequencies
+                    .iter()
+                    .any(WiphyFrequency::is_usable_for_ap)
+        })
+    }
+
+    /// Split dumps spread the attributes of a wiphy over several messages,
+    /// a band may even continue in the next message.
+    fn merge(&mut self, payload: &Genlmsghdr<Nl80211Cmd, Nl80211Attr>) {
+        let mut attrs = payload.get_attr_handle();
+
+        if let Ok(index) = attrs.get_attr_payload_as(Nl80211Attr::Wiphy) {
+            self.index = index;
+        }
+
+        if let Ok(name) = attrs.get_attr_payload_as_with_len(Nl80211Attr::WiphyName) {
+            self.name = name;
+        }
+
+        if let Ok(max_scan_ssids) = attrs.get_attr_payload_as(Nl80211Attr::MaxNumScanSsids) {
+            self.max_scan_ssids = max_scan_ssids;
+        }
+
+        if let Ok(features) = attrs.get_attr_payload_as::<u32>(Nl80211Attr::FeatureFlags) {
+            self.scan_random_mac = features & consts::NL80211_FEATURE_SCAN_RANDOM_MAC_ADDR != 0;
+            self.scan_low_priority = features & consts::NL80211_FEATURE_LOW_PRIORITY_SCAN != 0;
+        }
+
+        if let Some(attr) = attrs.get_attribute(Nl80211Attr::ExtFeatures) {
+            let ext_features = attr.payload().as_ref();
+            self.scan_low_span =
+                has_ext_feature(ext_features, consts::NL80211_EXT_FEATURE_LOW_SPAN_SCAN);
+        }
+
+        if let Some(attr) = attrs.get_attribute(Nl80211Attr::CipherSuites) {
+            self.cipher_suites = attr
+                .payload()
+                .as_ref()
+                .chunks_exact(4)
+                .map(|suite| {
+                    cipher_suite_name(u32::from_ne_bytes([suite[0], suite[1], suite[2], suite[3]]))
+                })
+                .collect();
+        }
+
+        if let Ok(iftypes) = attrs.get_nested_attributes::<u16>(Nl80211Attr::SupportedIftypes) {
+            self.iftypes = iftypes.iter().map(attr_iftype).collect();
+        }
+
+        if let Ok(combinations) =
+            attrs.get_nested_attributes::<u16>(Nl80211Attr::InterfaceCombinations)
+        {
+            self.combinations = combinations
+                .iter()
+                .filter_map(|attr| parse_combination(attr).ok())
+                .collect();
+        }
+
+        if let Ok(bands) = attrs.get_nested_attributes::<u16>(Nl80211Attr::WiphyBands) {
+            for band_attr in bands.iter() {
+                let index = band_attr.nla_type.nla_type;
+                let frequencies = parse_band_frequencies(band_attr).unwrap_or_default();
+
+                if let Some(band) = self.bands.iter_mut().find(|band| band.index == index) {
+                    band.frequencies.extend(frequencies);
+                } else {
+                    self.bands.push(WiphyBand {
+                        band: Band::from_nl80211(index),
+                        frequencies,
+                        index,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl InterfaceCombination {
+    /// Each interface takes a slot of one limit, so an access point and a
+    /// station either need slots in two different limits, or two slots in a
+    /// limit shared by both types.
+    fn allows_ap_and_station(&self) -> bool {
+        if self.max_interfaces < 2 {
+            return false;
+        }
+
+        let allows = |limit: &InterfaceLimit, iftype: InterfaceType| {
+            limit.max >= 1 && limit.iftypes.contains(&iftype)
+        };
+
+        self.limits.iter().enumerate().any(|(ap_index, ap_limit)| {
+            allows(ap_limit, InterfaceType::AP)
+                && self
+                    .limits
+                    .iter()
+                    .enumerate()
+                    .any(|(sta_index, sta_limit)| {
+                        allows(sta_limit, InterfaceType::Station)
+                            && (sta_index != ap_index || sta_limit.max >= 2)
+                    })
+        })
+    }
+}
+
+pub async fn get_wiphy(session: &Nl80211Session, interface: &str) -> Result<Wiphy> {
+    let iface = find_interface(session, interface).await?;
+
+    let wiphy = dump_wiphy(session, iface.wiphy)
+        .await
+        .context("Failed to get wiphy")?;
+
+    if wiphy.name.is_empty() {
+        bail!("No wiphy information received");
+    }
+
+    Ok(wiphy)
+}
+
+pub(super) async fn dump_wiphy(session: &Nl80211Session, wiphy_index: u32) -> Result<Wiphy> {
+    let nl_msghdr = create_get_wiphy_message(session.nl_id(), wiphy_index)?;
+
+    let mut wiphy = Wiphy::default();
+
+    session
+        .dump(nl_msghdr, |payload| {
+            wiphy.merge(&payload);
+            None::<()>
+        })
+        .await
+        .context("Failed to receive get wiphy response")?;
+
+    Ok(wiphy)
+}
+
+fn create_get_wiphy_message(
+    nl_id: u16,
+    wiphy_index: u32,
+) -> Result<Nlmsghdr<u16, Genlmsghdr<Nl80211Cmd, Nl80211Attr>>> {
+    let wiphy_attr = Nlattr::new(false, true, Nl80211Attr::Wiphy, wiphy_index)
+        .context("Failed to create wiphy attribute")?;
+    // Without the split dump newer kernels leave out most of the
+    // capabilities as they do not fit into a single message
+    let split_attr = Nlattr::new(false, true, Nl80211Attr::SplitWiphyDump, Buffer::new())
+        .context("Failed to create split wiphy dump attribute")?;
+    let genl_msghdr = Genlmsghdr::new(
+        Nl80211Cmd::GetWiphy,
+        1,
+        [wiphy_attr, split_attr].into_iter().collect(),
+    );
+
+    let flags = NlmFFlags::new(&[NlmF::Request, NlmF::Dump]);
+    let payload = NlPayload::Payload(genl_msghdr);
+    Ok(Nlmsghdr::new(None, nl_id, flags, None, None, payload))
+}
+
+/// Extended features are a bitmap indexed by feature number.
+fn has_ext_feature(ext_features: &[u8], feature: u32) -> bool {
+    let byte = usize::try_from(feature / 8).unwrap_or(usize::MAX);
+
+    ext_features
+        .get(byte)
+        .map_or(false, |bits| bits & (1 << (feature % 8)) != 0)
+}
+
+fn parse_band_frequencies(band_attr: &Nlattr<u16, Buffer>) -> Result<Vec<WiphyFrequency>> {
+    let mut band_attrs = band_attr.get_attr_handle::<Nl80211BandAttr>()?;
+    let freq_attrs = band_attrs.get_nested_attributes::<u16>(Nl80211BandAttr::Freqs)?;
+
+    Ok(freq_attrs
+        .iter()
+        .filter_map(|attr| parse_frequency(attr).ok())
+        .collect())
+}
+
+fn parse_frequency(freq_attr: &Nlattr<u16, Buffer>) -> Result<WiphyFrequency> {
+    let attrs = freq_attr.get_attr_handle::<Nl80211FrequencyAttr>()?;
+    let frequency = attrs.get_attr_payload_as(Nl80211FrequencyAttr::Freq)?;
+    let max_tx_power_mbm = attrs
+        .get_attr_payload_as::<u32>(Nl80211FrequencyAttr::MaxTxPower)
+        .ok();
+
+    Ok(WiphyFrequency {
+        frequency,
+        channel: frequency_to_channel(frequency),
+        disabled: attrs
+            .get_attribute(Nl80211FrequencyAttr::Disabled)
+            .is_some(),
+        no_ir: attrs.get_attribute(Nl80211FrequencyAttr::NoIr).is_some(),
+        radar: attrs.get_attribute(Nl80211FrequencyAttr::Radar).is_some(),
+        max_tx_power_dbm: max_tx_power_mbm.map(|mbm| mbm / 100),
+    })
+}
+
+fn parse_combination(comb_attr: &Nlattr<u16, Buffer>) -> Result<InterfaceCombination> {
+    let mut attrs = comb_attr.get_attr_handle::<Nl80211IfaceCombAttr>()?;
+
+    let max_interfaces = attrs.get_attr_payload_as(Nl80211IfaceCombAttr::Maxnum)?;
+    let num_channels = attrs.get_attr_payload_as(Nl80211IfaceCombAttr::NumChannels)?;
+    let sta_ap_beacon_int_match = attrs
+        .get_attribute(Nl80211IfaceCombAttr::StaApBiMatch)
+        .is_some();
+
+    let limits = attrs
+        .get_nested_attributes::<u16>(Nl80211IfaceCombAttr::Limits)?
+        .iter()
+        .filter_map(|attr| parse_limit(attr).ok())
+        .collect();
+
+    Ok(InterfaceCombination {
+        limits,
+        max_interfaces,
+        num_channels,
+        sta_ap_beacon_int_match,
+    })
+}
+
+fn parse_limit(limit_attr: &Nlattr<u16, Buffer>) -> Result<InterfaceLimit> {
+    let mut attrs = limit_attr.get_attr_handle::<Nl80211IfaceLimitAttr>()?;
+
+    let max = attrs.get_attr_payload_as(Nl80211IfaceLimitAttr::Max)?;
+    let iftypes = attrs
+        .get_nested_attributes::<u16>(Nl80211IfaceLimitAttr::Types)?
+        .iter()
+        .map(attr_iftype)
+        .collect();
+
+    Ok(InterfaceLimit { max, iftypes })
+}
+
+/// Interface types are listed as flag attributes typed by the interface type.
+fn attr_iftype(attr: &Nlattr<u16, Buffer>) -> InterfaceType {
+    InterfaceType::from(u32::from(attr.nla_type.nla_type))
+}
+
+fn cipher_suite_name(suite: u32) -> String {
+    let name = match suite {
+        0x000f_ac01 => "WEP-40",
+        0x000f_ac02 => "TKIP",
+        0x000f_ac04 => "CCMP-128",
+        0x000f_ac05 => "WEP-104",
+        0x000f_ac06 => "BIP-CMAC-128",
+        0x000f_ac08 => "GCMP-128",
+        0x000f_ac09 => "GCMP-256",
+        0x000f_ac0a => "CCMP-256",
+        0x000f_ac0b => "BIP-GMAC-128",
+        0x000f_ac0c => "BIP-GMAC-256",
+        0x000f_ac0d => "BIP-CMAC-256",
+        _ => return format!("{:08x}", suite),
+    };
+
+    name.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combination(
+        max_interfaces: u32,
+        limits: &[(u32, &[InterfaceType])],
+    ) -> InterfaceCombination {
+        InterfaceCombination {
+            limits: limits
+                .iter()
+                .map(|(max, iftypes)| InterfaceLimit {
+                    max: *max,
+                    iftypes: iftypes.to_vec(),
+                })
+                .collect(),
+            max_interfaces,
+            num_channels: 1,
+            sta_ap_beacon_int_match: false,
+        }
+    }
+
+    #[test]
+    fn single_shared_slot_does_not_allow_ap_and_station() {
+        let combination = combination(2, &[(1, &[InterfaceType::Station, InterfaceType::AP])]);
+
+        assert!(!combination.allows_ap_and_station());
+    }
+
+    #[test]
+    fn two_shared_slots_allow_ap_and_station() {
+        let combination = combination(2, &[(2, &[InterfaceType::Station, InterfaceType::AP])]);
+
+        assert!(combination.allows_ap_and_station());
+    }
+
+    #[test]
+    fn separate_limits_allow_ap_and_station() {
+        let combination = combination(
+            2,
+            &[
+                (1, &[InterfaceType::Station]),
+                (1, &[InterfaceType::AP, InterfaceType::P2PGo]),
+            ],
+        );
+
+        assert!(combination.allows_ap_and_station());
+    }
+
+    #[test]
+    fn separate_limits_need_two_interfaces() {
+        let combination = combination(
+            1,
+            &[(1, &[InterfaceType::Station]), (1, &[InterfaceType::AP])],
+        );
+
+        assert!(!combination.allows_ap_and_station());
+    }
+
+    #[test]
+    fn missing_station_limit_does_not_allow_ap_and_station() {
+        let combination = combination(2, &[(2, &[InterfaceType::AP])]);
+
+        assert!(!combination.allows_ap_and_station());
+    }
+}
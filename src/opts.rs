@@ -1,9 +1,65 @@
-use clap::Parser;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{ArgEnum, Parser};
 
 const DEFAULT_GATEWAY: &str = "192.168.42.1";
 const DEFAULT_SSID: &str = "WiFiConnect";
+const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0";
+const DEFAULT_LISTEN_PORT: &str = "3000";
+const DEFAULT_ADMIN_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_START_WHEN: &str = "always";
+const DEFAULT_START_GRACE_PERIOD: &str = "20";
+const DEFAULT_SCAN_TIMEOUT: &str = "15";
+const DEFAULT_DHCP_MODE: &str = "builtin";
+const DEFAULT_DHCP_LEASE_TIME: &str = "600";
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DhcpMode {
+    /// Serve portal clients from the embedded DHCP server
+    Builtin,
+    /// Let NetworkManager share the connection. Its DNS forwards queries
+    /// instead of resolving all names to the gateway, so clients are not
+    /// redirected to the portal
+    Shared,
+    /// Do not offer addresses to portal clients
+    Disabled,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StartWhen {
+    /// Always start the portal
+    Always,
+    /// Start the portal when NetworkManager reports no full connectivity
+    NoConnectivity,
+    /// Start the portal when there are no saved WiFi connections
+    NoSavedWifi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApChannel {
+    /// Pick the least crowded 2.4GHz channel
+    Auto,
+    Fixed(u32),
+}
 
-#[derive(Parser)]
+impl FromStr for ApChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+
+        match s.parse() {
+            Ok(channel) if channel > 0 => Ok(Self::Fixed(channel)),
+            _ => Err("expected 'auto' or a channel number".to_owned()),
+        }
+    }
+}
+
+#[derive(Parser, Clone)]
 pub struct Opts {
     #[clap(short, long, default_value = DEFAULT_SSID)]
     pub ssid: String,
@@ -16,4 +72,72 @@ pub struct Opts {
 
     #[clap(short, long)]
     pub interface: Option<String>,
+
+    /// Run the portal on a virtual interface with this name, e.g. `ap0`,
+    /// created on the same radio, so that credentials can be tested while the
+    /// portal stays up. Requires a radio supporting concurrent access point
+    /// and station mode
+    #[clap(long)]
+    pub ap_interface: Option<String>,
+
+    /// Channel of the portal access point on the 2.4GHz or 5GHz band, `auto`
+    /// picks the least crowded 2.4GHz channel from a channel survey and the
+    /// last scan [default: let NetworkManager choose]
+    #[clap(long)]
+    pub ap_channel: Option<ApChannel>,
+
+    /// When to start the portal after launch
+    #[clap(long, arg_enum, default_value = DEFAULT_START_WHEN)]
+    pub start_when: StartWhen,
+
+    /// Seconds to wait for NetworkManager to autoconnect before starting the
+    /// portal with `--start-when no-connectivity`
+    #[clap(long, default_value = DEFAULT_START_GRACE_PERIOD)]
+    pub start_grace_period: u32,
+
+    /// Seconds to wait for an nl80211 scan to complete
+    #[clap(long, default_value = DEFAULT_SCAN_TIMEOUT)]
+    pub scan_timeout: u64,
+
+    /// Address to serve the portal and the API on, can be given multiple times
+    #[clap(long = "listen-address", default_value = DEFAULT_LISTEN_ADDRESS)]
+    pub listen_addresses: Vec<IpAddr>,
+
+    #[clap(long, default_value = DEFAULT_LISTEN_PORT)]
+    pub listen_port: u16,
+
+    /// Listen only on the gateway address of the portal network
+    #[clap(long)]
+    pub listen_gateway_only: bool,
+
+    /// Additional port to serve the API on, e.g. when the portal uses port 80
+    #[clap(long)]
+    pub admin_port: Option<u16>,
+
+    #[clap(long, default_value = DEFAULT_ADMIN_ADDRESS)]
+    pub admin_address: IpAddr,
+
+    /// Stop the portal and exit with code 3 after this many seconds without
+    /// HTTP requests or associated clients
+    #[clap(long)]
+    pub activity_timeout: Option<u64>,
+
+    /// Directory with a custom web UI served instead of the built-in one
+    #[clap(long)]
+    pub ui_directory: Option<PathBuf>,
+
+    #[clap(long, arg_enum, default_value = DEFAULT_DHCP_MODE)]
+    pub dhcp: DhcpMode,
+
+    /// First address leased to portal clients [default: gateway network .2]
+    #[clap(long)]
+    pub dhcp_range_start: Option<Ipv4Addr>,
+
+    /// Last address leased to portal clients [default: gateway network .254]
+    #[clap(long)]
+    pub dhcp_range_end: Option<Ipv4Addr>,
+
+    /// Lease time in seconds
+    #[clap(long, default_value = DEFAULT_DHCP_LEASE_TIME)]
+    pub dhcp_lease_time: u32,
 }
@@ -0,0 +1,215 @@
+use std::fmt::Write;
+
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+
+use serde::Deserialize;
+
+use crate::error::ConnectionFailed;
+use crate::network::{CommandResponce, Connect, NetworkList, Security};
+use crate::web::{app_error_status, AppResponse};
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:28rem;margin:0 auto;padding:1rem;\
+color:#2a2d33}label{display:block;margin:.5rem 0 .25rem}input[type=text],\
+input[type=password]{width:100%;padding:.5rem;box-sizing:border-box}ul{list-style:none;\
+padding:0}li{padding:.4rem 0;border-bottom:1px solid #e6e8eb}button{margin-top:1rem;\
+padding:.6rem 1rem;width:100%}.error{color:#c62828}";
+
+/// Form submitted by the server rendered network list. The SSID is either
+/// picked from the list or typed into the "other network" field.
+#[derive(Deserialize)]
+pub struct ConnectForm {
+    pub ssid: Option<String>,
+    pub other_ssid: Option<String>,
+    pub passphrase: Option<String>,
+    pub identity: Option<String>,
+    /// Checkbox, only submitted when checked
+    pub hidden: Option<String>,
+}
+
+impl ConnectForm {
+    pub fn selected_ssid(&self) -> Option<String> {
+        self.ssid
+            .clone()
+            .filter(|ssid| !ssid.is_empty())
+            .or_else(|| self.other_ssid.clone().filter(|ssid| !ssid.is_empty()))
+    }
+}
+
+/// Renders the list of networks as a plain HTML form that works without
+/// JavaScript.
+pub fn networks_page(response: AppResponse) -> Response {
+    match response {
+        AppResponse::Network(CommandResponce::ListWiFiNetworks(networks)) => page(
+            StatusCode::OK,
+            "Choose a network",
+            &networks_form(&networks),
+        ),
+        response => unexpected_response_page(response),
+    }
+}
+
+/// Renders the outcome of a connect request submitted from the network list.
+pub fn connect_page(response: AppResponse) -> Response {
+    match response {
+        AppResponse::Network(CommandResponce::Connect(connect)) => connected_page(&connect),
+        AppResponse::Error(err) => match err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<ConnectionFailed>())
+        {
+            Some(failed) => connection_failed_page(failed),
+            None => unexpected_response_page(AppResponse::Error(err)),
+        },
+        response => unexpected_response_page(response),
+    }
+}
+
+pub fn missing_ssid_page() -> Response {
+    page(
+        StatusCode::BAD_REQUEST,
+        "No network selected",
+        "<p class=\"error\">Please choose a network or enter its name.</p>\
+         <p><a href=\"/networks\">Back to the network list</a></p>",
+    )
+}
+
+fn networks_form(networks: &NetworkList) -> String {
+    let mut body = String::new();
+
+    body.push_str("<form method=\"post\" action=\"/networks/connect\"><ul>");
+
+    for station in &networks.stations {
+        let ssid = escape_html(&station.ssid);
+        let _ = write!(
+            body,
+            "<li><label><input type=\"radio\" name=\"ssid\" value=\"{}\"> {} ({}, {}%)</label></li>",
+            ssid,
+            ssid,
+            security_label(station.security),
+            station.quality
+        );
+    }
+
+    if !networks.hidden_channels.is_empty() {
+        let channels: Vec<String> = networks
+            .hidden_channels
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let _ = write!(
+            body,
+            "<li>Hidden networks on channel {}, enter the name below</li>",
+            channels.join(", ")
+        );
+    }
+
+    body.push_str(
+        "<li><label><input type=\"radio\" name=\"ssid\" value=\"\"> Other network</label>\
+         <input type=\"text\" name=\"other_ssid\" autocomplete=\"off\">\
+         <label><input type=\"checkbox\" name=\"hidden\"> Hidden network</label></li></ul>\
+         <label for=\"identity\">Username (enterprise networks only)</label>\
+         <input type=\"text\" id=\"identity\" name=\"identity\" autocomplete=\"username\">\
+         <label for=\"passphrase\">Password</label>\
+         <input type=\"password\" id=\"passphrase\" name=\"passphrase\">\
+         <button type=\"submit\">Connect</button></form>\
+         <p><a href=\"/networks\">Refresh</a></p>",
+    );
+
+    body
+}
+
+fn security_label(security: Security) -> &'static str {
+    match security {
+        Security::Open => "open",
+        Security::Wep => "WEP",
+        Security::Wpa => "WPA",
+        Security::Wpa2 => "WPA2",
+        Security::Wpa3 => "WPA3",
+        Security::Owe => "enhanced open",
+        Security::Enterprise => "enterprise",
+    }
+}
+
+fn connected_page(connect: &Connect) -> Response {
+    page(
+        StatusCode::OK,
+        "Connected",
+        &format!(
+            "<p>The device is now connected to <b>{}</b>.</p>",
+            escape_html(&connect.ssid)
+        ),
+    )
+}
+
+fn connection_failed_page(failed: &ConnectionFailed) -> Response {
+    page(
+        StatusCode::BAD_GATEWAY,
+        "Connection failed",
+        &format!(
+            "<p class=\"error\">Could not connect to <b>{}</b>. \
+             Check the password and try again.</p>\
+             <p><a href=\"/networks\">Back to the network list</a></p>",
+            escape_html(&failed.ssid)
+        ),
+    )
+}
+
+/// Errors get the status the JSON API would report. Their messages are
+/// shown, so that invalid input can be corrected.
+fn unexpected_response_page(response: AppResponse) -> Response {
+    let mut body = String::new();
+    let mut status = StatusCode::INTERNAL_SERVER_ERROR;
+
+    if let AppResponse::Error(err) = response {
+        status = app_error_status(&err);
+
+        for cause in err.chain() {
+            let _ = write!(
+                body,
+                "<p class=\"error\">{}</p>",
+                escape_html(&cause.to_string())
+            );
+        }
+    }
+
+    body.push_str("<p><a href=\"/networks\">Back to the network list</a></p>");
+
+    let title = if status.is_client_error() {
+        "Please check your input"
+    } else {
+        "Something went wrong"
+    };
+
+    page(status, title, &body)
+}
+
+fn page(status: StatusCode, title: &str, body: &str) -> Response {
+    let html = format!(
+        "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>WiFi Connect</title><style>{}</style></head>\
+         <body><h1>{}</h1>{}</body></html>",
+        STYLE, title, body
+    );
+
+    (status, Html(html)).into_response()
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        HeaderValue, Response, StatusCode,
+    },
+    response::IntoResponse,
+    routing::{get, get_service, MethodRouter},
+    Router,
+};
+
+use tower_http::services::{ServeDir, ServeFile};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+const INDEX_HTML: &str = "index.html";
+
+const EMBEDDED_INDEX_HTML: &[u8] = include_bytes!("../ui/index.html");
+const EMBEDDED_APP_JS: &[u8] = include_bytes!("../ui/app.js");
+const EMBEDDED_STYLE_CSS: &[u8] = include_bytes!("../ui/style.css");
+
+const CACHE_CONTROL_HTML: &str = "no-cache";
+const CACHE_CONTROL_ASSETS: &str = "public, max-age=3600";
+
+/// Serves the default setup UI compiled into the binary.
+pub fn embedded_ui_router() -> Router {
+    Router::new()
+        .route("/", get(embedded_index_html))
+        .route("/app.js", get(embedded_app_js))
+        .route("/style.css", get(embedded_style_css))
+}
+
+async fn embedded_index_html() -> impl IntoResponse {
+    embedded_asset("text/html; charset=utf-8", EMBEDDED_INDEX_HTML)
+}
+
+async fn embedded_app_js() -> impl IntoResponse {
+    embedded_asset("application/javascript; charset=utf-8", EMBEDDED_APP_JS)
+}
+
+async fn embedded_style_css() -> impl IntoResponse {
+    embedded_asset("text/css; charset=utf-8", EMBEDDED_STYLE_CSS)
+}
+
+fn embedded_asset(content_type: &'static str, content: &'static [u8]) -> impl IntoResponse {
+    (
+        [
+            (CONTENT_TYPE, content_type),
+            (CACHE_CONTROL, CACHE_CONTROL_HTML),
+        ],
+        content,
+    )
+}
+
+/// Serves the static files of a user provided UI directory. Unknown paths
+/// fall back to `index.html`, so that client side routing keeps working.
+pub fn ui_directory_service(directory: &Path) -> MethodRouter<Body> {
+    let index = ServeFile::new(directory.join(INDEX_HTML));
+    let serve_dir = ServeDir::new(directory).fallback(index);
+
+    get_service(serve_dir)
+        .handle_error(handle_io_error)
+        .layer(SetResponseHeaderLayer::overriding(
+            CACHE_CONTROL,
+            cache_control,
+        ))
+}
+
+/// HTML pages have to be revalidated so that updated UI versions are picked
+/// up, while the rest of the assets can be cached for a while.
+fn cache_control<B>(response: &Response<B>) -> Option<HeaderValue> {
+    if !response.status().is_success() {
+        return None;
+    }
+
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("text/html"));
+
+    if is_html {
+        Some(HeaderValue::from_static(CACHE_CONTROL_HTML))
+    } else {
+        Some(HeaderValue::from_static(CACHE_CONTROL_ASSETS))
+    }
+}
+
+async fn handle_io_error(err: io::Error) -> impl IntoResponse {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Failed to serve UI file: {}", err),
+    )
+}
@@ -1,22 +1,50 @@
+use std::mem::size_of;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 
 use axum::{
-    extract,
-    http::StatusCode,
+    async_trait,
+    body::HttpBody,
+    extract::{self, FromRequest, RequestParts},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
-    routing::get,
-    Extension, Json, Router,
+    routing::{get, post},
+    BoxError, Extension, Form, Json, Router,
 };
 
+use tokio::net::TcpSocket;
 use tokio::signal::unix::{signal, SignalKind};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 
-use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-use crate::network::{Command, CommandRequest, CommandResponce};
+use crate::activity::{track_activity, wait_for_inactivity, Activity};
+use crate::captive::{handle_captive_probes, CaptiveState};
+use crate::error::{ConnectionFailed, InvalidRequest};
+use crate::network::{Command, CommandRequest, CommandResponce, NetworkInfo, PortalState};
 use crate::nl80211;
+use crate::nl80211::error::{ErrorKind, Nl80211Error};
+use crate::nl80211::scan::{ScanError, ScanOptions};
+use crate::nl80211::session::{Nl80211Session, NL80211_NOT_AVAILABLE};
+use crate::opts::Opts;
+use crate::pages::{self, ConnectForm};
+use crate::ui::{embedded_ui_router, ui_directory_service};
+
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// Why the web server stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    Shutdown,
+    ActivityTimeout,
+}
 
 pub enum AppResponse {
     Network(CommandResponce),
@@ -26,51 +54,309 @@ pub enum AppResponse {
 #[derive(Serialize)]
 pub struct AppErrors {
     pub errors: Vec<String>,
+    /// Error number reported by the kernel for failed nl80211 requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
 }
 
 impl AppErrors {
-    fn new(errors: Vec<String>) -> Self {
-        Self { errors }
+    fn new(errors: Vec<String>, errno: Option<i32>) -> Self {
+        Self { errors, errno }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConnectRequest {
+    pub ssid: String,
+    pub passphrase: Option<String>,
+    pub identity: Option<String>,
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RescanRequest {
+    /// Hidden networks to probe for
+    #[serde(default)]
+    pub ssids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScanQuery {
+    /// Hidden network to probe for
+    pub ssid: Option<String>,
+    /// Comma separated frequencies in MHz
+    pub frequencies: Option<String>,
+    /// Comma separated bands, e.g. `2.4GHz,5GHz`
+    pub bands: Option<String>,
+    #[serde(default)]
+    pub passive: bool,
+    #[serde(default)]
+    pub low_priority: bool,
+    #[serde(default)]
+    pub low_span: bool,
+    #[serde(default)]
+    pub random_mac: bool,
+}
+
+impl ScanQuery {
+    fn into_options(self) -> Result<ScanOptions> {
+        Ok(ScanOptions {
+            ssids: self.ssid.into_iter().collect(),
+            frequencies: parse_list(self.frequencies.as_deref()).context("Invalid frequencies")?,
+            bands: parse_list(self.bands.as_deref()).context("Invalid bands")?,
+            passive: self.passive,
+            low_priority: self.low_priority,
+            low_span: self.low_span,
+            random_mac: self.random_mac,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DisconnectClientRequest {
+    pub mac: String,
+}
+
+/// Extracts the request body either as JSON or as an URL encoded form
+/// depending on the content type of the request.
+pub struct JsonOrForm<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for JsonOrForm<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.starts_with("application/json"));
+
+        if is_json {
+            let Json(payload) = Json::<T>::from_request(req)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(payload))
+        } else {
+            let Form(payload) = Form::<T>::from_request(req)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(payload))
+        }
     }
 }
 
 struct MainState {
     glib_sender: glib::Sender<CommandRequest>,
     shutdown_opt: Mutex<Option<oneshot::Sender<()>>>,
+    session: Option<Nl80211Session>,
+    interface: String,
+    portal_interface: String,
+    scan_timeout: Duration,
+}
+
+impl MainState {
+    fn session(&self) -> Result<&Nl80211Session> {
+        self.session.as_ref().context(NL80211_NOT_AVAILABLE)
+    }
 }
 
-pub async fn run_web_loop(glib_sender: glib::Sender<CommandRequest>) {
+pub async fn run_web_loop(
+    opts: Opts,
+    gateway: Ipv4Addr,
+    network_info: NetworkInfo,
+    session: Option<Nl80211Session>,
+    glib_sender: glib::Sender<CommandRequest>,
+    portal_state: watch::Receiver<PortalState>,
+) -> Result<ExitReason> {
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
+    let captive_state = CaptiveState::new(portal_state.clone(), gateway);
+
+    let activity = Activity::new();
+
     let shared_state = Arc::new(MainState {
         glib_sender: glib_sender.clone(),
         shutdown_opt: Mutex::new(Some(shutdown_tx)),
+        session: session.clone(),
+        interface: network_info.interface,
+        portal_interface: network_info.portal_interface.clone(),
+        scan_timeout: Duration::from_secs(opts.scan_timeout),
     });
 
     let app = Router::new()
-        .route("/", get(usage))
         .route("/check-connectivity", get(check_connectivity))
         .route("/list-connections", get(list_connections))
         .route("/list-wifi-networks", get(list_wifi_networks))
+        .route("/rescan", post(rescan))
+        .route("/connect", post(connect))
+        .route("/networks", get(networks_page))
+        .route("/networks/connect", post(connect_page))
         .route("/shutdown", get(shutdown))
         .route("/stop", get(stop))
         .route("/scan", get(scan))
-        .layer(Extension(shared_state));
+        .route("/wiphy", get(wiphy))
+        .route("/link", get(link))
+        .route("/portal/clients", get(portal_clients))
+        .route("/portal/clients/disconnect", post(disconnect_portal_client));
+
+    let app = if let Some(ref ui_directory) = opts.ui_directory {
+        println!("Serving UI from {}", ui_directory.display());
+        app.fallback(ui_directory_service(ui_directory))
+    } else {
+        app.merge(embedded_ui_router())
+    };
+
+    // Requests on the admin port do not keep the portal up
+    let admin_app = app.clone();
+    let portal_app = app.layer(middleware::from_fn({
+        let activity = activity.clone();
+        move |req, next| track_activity(req, next, activity.clone())
+    }));
+
+    let [portal_app, admin_app] = [portal_app, admin_app].map(|app| {
+        let captive_state = captive_state.clone();
+
+        app.layer(middleware::from_fn(move |req, next| {
+            handle_captive_probes(req, next, captive_state.clone())
+        }))
+        .layer(Extension(shared_state.clone()))
+    });
 
-    let server =
-        axum::Server::bind(&"0.0.0.0:3000".parse().unwrap()).serve(app.into_make_service());
+    let admin_address = opts
+        .admin_port
+        .map(|admin_port| SocketAddr::new(opts.admin_address, admin_port));
 
-    let graceful = server.with_graceful_shutdown(shutdown_signal(shutdown_rx, glib_sender));
+    let listeners = listen_addresses(&opts, gateway)
+        .into_iter()
+        .map(|address| (address, &portal_app))
+        .chain(admin_address.map(|address| (address, &admin_app)));
 
     println!("Web server starting...");
 
-    graceful.await.unwrap();
+    let (stop_tx, stop_rx) = watch::channel(false);
+
+    let mut servers = Vec::new();
+
+    for (address, app) in listeners {
+        let listener = bind_listener(address, address.ip() == gateway)
+            .context(format!("Failed to listen on {}", address))?;
+
+        let mut stop_rx = stop_rx.clone();
+
+        let server = axum::Server::from_tcp(listener)
+            .context(format!("Failed to start web server on {}", address))?
+            .serve(app.clone().into_make_service())
+            .with_graceful_shutdown(async move {
+                stop_rx.changed().await.ok();
+            });
+
+        println!("Listening on {}", address);
+
+        servers.push(tokio::spawn(server));
+    }
+
+    let inactivity = async {
+        match opts.activity_timeout {
+            Some(timeout) => {
+                let timeout = Duration::from_secs(timeout);
+                wait_for_inactivity(
+                    activity,
+                    timeout,
+                    session,
+                    network_info.portal_interface,
+                    portal_state,
+                )
+                .await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    let exit_reason = tokio::select! {
+        _ = shutdown_signal(shutdown_rx) => ExitReason::Shutdown,
+        _ = inactivity => ExitReason::ActivityTimeout,
+    };
+
+    println!("Shutting down...");
+
+    send_command(&glib_sender, Command::Stop).await;
+
+    stop_tx.send_replace(true);
+
+    for server in servers {
+        server
+            .await
+            .context("Failed to join web server task")?
+            .context("Web server failed")?;
+    }
+
+    println!("Quit.");
+
+    Ok(exit_reason)
 }
 
-async fn shutdown_signal(
-    shutdown_rx: oneshot::Receiver<()>,
-    glib_sender: glib::Sender<CommandRequest>,
-) {
+/// Addresses the portal is served on, without the admin port.
+fn listen_addresses(opts: &Opts, gateway: Ipv4Addr) -> Vec<SocketAddr> {
+    if opts.listen_gateway_only {
+        vec![SocketAddr::from((gateway, opts.listen_port))]
+    } else {
+        opts.listen_addresses
+            .iter()
+            .map(|address| SocketAddr::new(*address, opts.listen_port))
+            .collect()
+    }
+}
+
+fn bind_listener(address: SocketAddr, freebind: bool) -> Result<std::net::TcpListener> {
+    let socket = if address.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+
+    socket.set_reuseaddr(true)?;
+
+    if freebind {
+        // The gateway address is only assigned while the portal is up
+        enable_freebind(&socket)?;
+    }
+
+    socket.bind(address)?;
+
+    let listener = socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(listener.into_std()?)
+}
+
+fn enable_freebind(socket: &TcpSocket) -> Result<()> {
+    let enable: libc::c_int = 1;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_IP,
+            libc::IP_FREEBIND,
+            (&enable as *const libc::c_int).cast(),
+            size_of::<libc::c_int>().try_into()?,
+        )
+    };
+
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to enable IP_FREEBIND");
+    }
+
+    Ok(())
+}
+
+async fn shutdown_signal(shutdown_rx: oneshot::Receiver<()>) {
     let mut interrupt = signal(SignalKind::interrupt()).unwrap();
     let mut terminate = signal(SignalKind::terminate()).unwrap();
     let mut quit = signal(SignalKind::quit()).unwrap();
@@ -83,16 +369,6 @@ async fn shutdown_signal(
         _ = quit.recv() => println!("SIGQUIT received"),
         _ = hangup.recv() => println!("SIGHUP received"),
     }
-
-    println!("Shutting down...");
-
-    send_command(&glib_sender, Command::Stop).await;
-
-    println!("Quit.");
-}
-
-async fn usage() -> &'static str {
-    "Use /check-connectivity or /list-connections\n"
 }
 
 async fn check_connectivity(state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
@@ -113,6 +389,62 @@ async fn list_wifi_networks(state: extract::Extension<Arc<MainState>>) -> impl I
         .into_response()
 }
 
+async fn rescan(
+    state: extract::Extension<Arc<MainState>>,
+    headers: HeaderMap,
+    request: Result<JsonOrForm<RescanRequest>, Response>,
+) -> Response {
+    // The body is optional, but one that cannot be parsed is rejected
+    let ssids = match request {
+        Ok(JsonOrForm(request)) => request.ssids,
+        Err(_) if !headers.contains_key(CONTENT_TYPE) => Vec::new(),
+        Err(rejection) => return rejection,
+    };
+
+    send_command(&state.0.glib_sender, Command::Rescan { ssids })
+        .await
+        .into_response()
+}
+
+async fn connect(
+    state: extract::Extension<Arc<MainState>>,
+    JsonOrForm(request): JsonOrForm<ConnectRequest>,
+) -> impl IntoResponse {
+    let command = Command::Connect {
+        ssid: request.ssid,
+        passphrase: non_empty(request.passphrase),
+        identity: non_empty(request.identity),
+        hidden: request.hidden,
+    };
+
+    send_command(&state.0.glib_sender, command)
+        .await
+        .into_response()
+}
+
+async fn networks_page(state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
+    pages::networks_page(send_command(&state.0.glib_sender, Command::ListWiFiNetworks).await)
+}
+
+async fn connect_page(
+    state: extract::Extension<Arc<MainState>>,
+    Form(form): Form<ConnectForm>,
+) -> impl IntoResponse {
+    let ssid = match form.selected_ssid() {
+        Some(ssid) => ssid,
+        None => return pages::missing_ssid_page(),
+    };
+
+    let command = Command::Connect {
+        ssid,
+        passphrase: non_empty(form.passphrase),
+        identity: non_empty(form.identity),
+        hidden: form.hidden.is_some(),
+    };
+
+    pages::connect_page(send_command(&state.0.glib_sender, command).await)
+}
+
 async fn shutdown(mut state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
     let response = send_command(&state.0.glib_sender, Command::Shutdown)
         .await
@@ -129,9 +461,144 @@ async fn stop(state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
         .into_response()
 }
 
-async fn scan(_: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
-    let stations = nl80211::scan::scan("wlan0").await.unwrap();
-    (StatusCode::OK, Json(stations)).into_response()
+async fn scan(
+    state: extract::Extension<Arc<MainState>>,
+    extract::Query(query): extract::Query<ScanQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let options = query.into_options()?;
+
+        nl80211::scan::scan(
+            state.0.session()?,
+            &state.0.interface,
+            &options,
+            state.0.scan_timeout,
+        )
+        .await
+    };
+
+    match result.await {
+        Ok(stations) => (StatusCode::OK, Json(stations)).into_response(),
+        Err(err) => AppResponse::Error(err.context("Failed to scan")).into_response(),
+    }
+}
+
+async fn wiphy(state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
+    let result = async { nl80211::wiphy::get_wiphy(state.0.session()?, &state.0.interface).await };
+
+    match result.await {
+        Ok(wiphy) => (StatusCode::OK, Json(wiphy)).into_response(),
+        Err(err) => AppResponse::Error(err.context("Failed to get wiphy")).into_response(),
+    }
+}
+
+async fn link(state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
+    let result = async { nl80211::link::get_link(state.0.session()?, &state.0.interface).await };
+
+    match result.await {
+        Ok(link) => (StatusCode::OK, Json(link)).into_response(),
+        Err(err) => AppResponse::Error(err.context("Failed to get link status")).into_response(),
+    }
+}
+
+async fn portal_clients(state: extract::Extension<Arc<MainState>>) -> impl IntoResponse {
+    let result = async {
+        nl80211::station::get_station_infos(state.0.session()?, &state.0.portal_interface).await
+    };
+
+    match result.await {
+        Ok(clients) => (StatusCode::OK, Json(clients)).into_response(),
+        Err(err) => {
+            AppResponse::Error(err.context("Failed to list portal clients")).into_response()
+        }
+    }
+}
+
+async fn disconnect_portal_client(
+    state: extract::Extension<Arc<MainState>>,
+    JsonOrForm(request): JsonOrForm<DisconnectClientRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let mac = request
+            .mac
+            .parse()
+            .map_err(|_| InvalidRequest::new(format!("Invalid MAC address '{}'", request.mac)))?;
+
+        let session = state.0.session()?;
+
+        nl80211::station::disconnect_station(session, &state.0.portal_interface, mac).await?;
+
+        nl80211::station::get_station_infos(session, &state.0.portal_interface).await
+    };
+
+    match result.await {
+        Ok(clients) => (StatusCode::OK, Json(clients)).into_response(),
+        Err(err) => {
+            AppResponse::Error(err.context("Failed to disconnect portal client")).into_response()
+        }
+    }
+}
+
+fn parse_list<T>(value: Option<&str>) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse::<T>().map_err(|err| {
+                let err: anyhow::Error = err.into();
+                InvalidRequest::new(format!("Failed to parse '{}': {:#}", item, err)).into()
+            })
+        })
+        .collect()
+}
+
+/// Tells client mistakes and retryable scan failures apart from server
+/// faults, by the first typed error found in the chain.
+pub fn app_error_status(err: &anyhow::Error) -> StatusCode {
+    for cause in err.chain() {
+        if cause.is::<InvalidRequest>() {
+            return StatusCode::BAD_REQUEST;
+        }
+
+        if cause.is::<ConnectionFailed>() {
+            return StatusCode::BAD_GATEWAY;
+        }
+
+        if let Some(scan_error) = cause.downcast_ref::<ScanError>() {
+            return match *scan_error {
+                ScanError::Aborted => StatusCode::SERVICE_UNAVAILABLE,
+                ScanError::TimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+            };
+        }
+
+        if let Some(nl80211_error) = cause.downcast_ref::<Nl80211Error>() {
+            return error_status(nl80211_error);
+        }
+    }
+
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+fn error_status(err: &Nl80211Error) -> StatusCode {
+    match err.kind() {
+        ErrorKind::Busy | ErrorKind::NetworkDown => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorKind::NotSupported => StatusCode::NOT_IMPLEMENTED,
+        ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        ErrorKind::NoDevice => StatusCode::NOT_FOUND,
+        ErrorKind::InvalidArgument => StatusCode::BAD_REQUEST,
+        ErrorKind::Other => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    // HTML forms submit empty strings for fields that were left blank
+    value.filter(|s| !s.is_empty())
 }
 
 async fn issue_shutdwon(state: &mut Arc<MainState>) {
@@ -147,6 +614,8 @@ async fn send_command(glib_sender: &glib::Sender<CommandRequest>, command: Comma
         Command::CheckConnectivity => "check connectivity",
         Command::ListConnections => "list actions",
         Command::ListWiFiNetworks => "list WiFi networks",
+        Command::Rescan { .. } => "rescan WiFi networks",
+        Command::Connect { .. } => "connect",
         Command::Shutdown => "shutdown",
         Command::Stop => "stop",
     };
@@ -187,8 +656,12 @@ impl IntoResponse for AppResponse {
         match self {
             AppResponse::Error(err) => {
                 let errors: Vec<String> = err.chain().map(|e| format!("{}", e)).collect();
-                let app_errors = AppErrors::new(errors);
-                (StatusCode::INTERNAL_SERVER_ERROR, Json(app_errors)).into_response()
+                let nl80211_error = err
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<Nl80211Error>());
+                let status = app_error_status(&err);
+                let app_errors = AppErrors::new(errors, nl80211_error.map(Nl80211Error::errno));
+                (status, Json(app_errors)).into_response()
             }
             AppResponse::Network(network_response) => match network_response {
                 CommandResponce::ListConnections(connections) => {
@@ -197,9 +670,12 @@ impl IntoResponse for AppResponse {
                 CommandResponce::CheckConnectivity(connectivity) => {
                     (StatusCode::OK, Json(connectivity)).into_response()
                 }
-                CommandResponce::ListWiFiNetworks(networks) => {
+                CommandResponce::ListWiFiNetworks(networks) | CommandResponce::Rescan(networks) => {
                     (StatusCode::OK, Json(networks)).into_response()
                 }
+                CommandResponce::Connect(connect) => {
+                    (StatusCode::OK, Json(connect)).into_response()
+                }
                 CommandResponce::Shutdown(shutdown) => {
                     (StatusCode::OK, Json(shutdown)).into_response()
                 }
@@ -0,0 +1,225 @@
+(function () {
+  'use strict';
+
+  // The setup network goes away once the device joins the selected network,
+  // so a connect request that never returns usually means success
+  var CONNECT_TIMEOUT_MS = 60000;
+
+  var SECURITY_LABELS = {
+    open: 'Open',
+    wep: 'WEP',
+    wpa: 'WPA',
+    wpa2: 'WPA2',
+    wpa3: 'WPA3',
+    owe: 'Enhanced Open',
+    enterprise: 'Enterprise'
+  };
+
+  var networks = document.getElementById('networks');
+  var networksStatus = document.getElementById('networks-status');
+  var refresh = document.getElementById('refresh');
+  var form = document.getElementById('connect-form');
+  var hiddenStatus = document.getElementById('hidden-status');
+  var ssid = document.getElementById('ssid');
+  var hiddenNetwork = document.getElementById('hidden-network');
+  var enterprise = document.getElementById('enterprise');
+  var identityField = document.getElementById('identity-field');
+  var identity = document.getElementById('identity');
+  var passphrase = document.getElementById('passphrase');
+  var connect = document.getElementById('connect');
+  var progress = document.getElementById('progress');
+  var spinner = document.getElementById('spinner');
+  var progressStatus = document.getElementById('progress-status');
+
+  function setStatus(element, text, isError) {
+    element.textContent = text;
+    element.className = isError ? 'status error' : 'status';
+  }
+
+  function errorMessage(body, fallback) {
+    if (body && body.errors && body.errors.length) {
+      return body.errors.join(': ');
+    }
+    return fallback;
+  }
+
+  function setPassphraseRequired(required) {
+    passphrase.disabled = !required;
+    if (!required) {
+      passphrase.value = '';
+    }
+  }
+
+  function selectNetwork(item, station) {
+    var selected = networks.querySelector('.selected');
+    if (selected) {
+      selected.classList.remove('selected');
+    }
+    item.classList.add('selected');
+    ssid.value = station.ssid;
+    hiddenNetwork.checked = false;
+
+    enterprise.checked = station.security === 'enterprise';
+    identityField.hidden = !enterprise.checked;
+
+    // Open networks are joined without a password
+    var isOpen = station.security === 'open' || station.security === 'owe';
+    setPassphraseRequired(!isOpen);
+
+    if (enterprise.checked) {
+      identity.focus();
+    } else if (!isOpen) {
+      passphrase.focus();
+    }
+  }
+
+  function renderHiddenChannels(channels) {
+    if (channels && channels.length) {
+      hiddenStatus.hidden = false;
+      setStatus(hiddenStatus, 'Hidden networks on channel ' + channels.join(', ') +
+        '. Enter the name below and tick "Hidden network".');
+    } else {
+      hiddenStatus.hidden = true;
+    }
+  }
+
+  function renderNetworks(stations) {
+    networks.innerHTML = '';
+
+    stations.forEach(function (station) {
+      var item = document.createElement('li');
+
+      var name = document.createElement('span');
+      name.textContent = station.ssid;
+      item.appendChild(name);
+
+      var signal = document.createElement('span');
+      signal.className = 'signal';
+      signal.textContent = (SECURITY_LABELS[station.security] || '') + ' ' + station.quality + '%';
+      item.appendChild(signal);
+
+      item.addEventListener('click', function () {
+        selectNetwork(item, station);
+      });
+
+      networks.appendChild(item);
+    });
+
+    if (stations.length) {
+      networksStatus.hidden = true;
+    } else {
+      networksStatus.hidden = false;
+      setStatus(networksStatus, 'No networks found. You can still enter a network name below.');
+    }
+  }
+
+  // A rescan takes a few seconds, so the initial list comes from the
+  // networks found at startup and a fresh scan is only run on request
+  function loadNetworks(rescan) {
+    networksStatus.hidden = false;
+    setStatus(networksStatus, rescan ? 'Scanning for networks…' : 'Loading networks…');
+    refresh.disabled = true;
+
+    var request = rescan ? fetch('/rescan', { method: 'POST' }) : fetch('/list-wifi-networks');
+
+    request
+      .then(function (response) {
+        return response.json().then(function (body) {
+          if (!response.ok) {
+            throw new Error(errorMessage(body, 'Failed to load networks'));
+          }
+          return body;
+        });
+      })
+      .then(function (body) {
+        renderNetworks(body.stations);
+        renderHiddenChannels(body.hidden_channels);
+      })
+      .catch(function (err) {
+        setStatus(networksStatus, err.message, true);
+      })
+      .then(function () {
+        refresh.disabled = false;
+      });
+  }
+
+  function showProgress(text, isError, done) {
+    progress.hidden = false;
+    spinner.className = done ? 'spinner done' : 'spinner';
+    setStatus(progressStatus, text, isError);
+  }
+
+  function submitConnect(event) {
+    event.preventDefault();
+
+    var request = { ssid: ssid.value };
+    if (passphrase.value) {
+      request.passphrase = passphrase.value;
+    }
+    if (enterprise.checked && identity.value) {
+      request.identity = identity.value;
+    }
+    if (hiddenNetwork.checked) {
+      request.hidden = true;
+    }
+
+    connect.disabled = true;
+    showProgress('Connecting to ' + request.ssid + '…');
+
+    var timedOut = false;
+    var timer = setTimeout(function () {
+      timedOut = true;
+      showProgress('The device is connecting to ' + request.ssid +
+        '. If the setup network disappears, the connection succeeded.', false, true);
+    }, CONNECT_TIMEOUT_MS);
+
+    fetch('/connect', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(request)
+    })
+      .then(function (response) {
+        return response.json().then(function (body) {
+          if (!response.ok) {
+            throw new Error(errorMessage(body, 'Failed to connect'));
+          }
+          return body;
+        });
+      })
+      .then(function (body) {
+        clearTimeout(timer);
+        showProgress('Connected to ' + body.ssid + '.', false, true);
+      })
+      .catch(function (err) {
+        clearTimeout(timer);
+        if (timedOut) {
+          return;
+        }
+        if (err instanceof TypeError) {
+          // The request was cut off, most likely because the setup network
+          // has been shut down to join the selected network
+          showProgress('The setup network has been shut down while connecting to ' +
+            request.ssid + '.', false, true);
+        } else {
+          showProgress(err.message, true, true);
+          connect.disabled = false;
+        }
+      });
+  }
+
+  ssid.addEventListener('input', function () {
+    // A typed name may not match any listed network
+    setPassphraseRequired(true);
+  });
+
+  enterprise.addEventListener('change', function () {
+    identityField.hidden = !enterprise.checked;
+  });
+
+  refresh.addEventListener('click', function () {
+    loadNetworks(true);
+  });
+  form.addEventListener('submit', submitConnect);
+
+  loadNetworks(false);
+})();